@@ -2,116 +2,323 @@ use std::fmt::Pointer;
 use bevy::input::mouse::{MouseMotion, MouseWheel};
 use bevy::math::{uvec2, vec2, vec3};
 use bevy::prelude::*;
+use bevy::ecs::system::SystemParam;
 use bevy::render::render_resource::{AsBindGroup, ShaderType};
+use bevy::utils::HashSet;
 use bevy::utils::tracing::Instrument;
 use bevy_fast_tilemap::{CustomFastTileMapPlugin, FastTileMapPlugin, Map, MapBundleManaged};
-use crate::ascii_world::{AsciiAddEvent, AsciiMoveEvent, AsciiRemoveEvent, AsciiTile, WorldSettings};
+use crate::ascii_world::{AsciiAddEvent, AsciiMoveEvent, AsciiRemoveEvent, AsciiTile, AsciiWorldSet, Appearance, Grid, Terrain, TerrainChangedEvent, UpdateViewLayerEvent, ViewLayer, WorldSettings};
+use crate::chunk::{ChunkEvent, CHUNK_SIZE};
+use crate::config::Config;
+use crate::fov::{CellVisibility, FovSet, Memory, Viewshed};
+use crate::input::{Action, ActionState};
+use crate::lighting::{LightingSet, LightMap};
 use crate::MainState;
 use crate::player::PlayerMarker;
+use crate::save::WorldLoadedEvent;
 
+/// Chunks the layers span each way, the camera's chunk in the middle.
+const WINDOW_CHUNKS: i32 = 5;
+const WINDOW_SIZE: IVec2 = IVec2::new(WINDOW_CHUNKS * CHUNK_SIZE.x, WINDOW_CHUNKS * CHUNK_SIZE.y);
+pub(crate) const TILE_SIZE: f32 = 16.;
+
+/// One map per layer of the world, covering the window of cells around the camera. The
+/// window moves a chunk at a time as the camera does, repainting the maps instead of
+/// allocating new ones.
 #[derive(Component)]
-struct Layers(Vec<Entity>);
-#[derive(Component)]
-pub struct ViewLayer(pub u32);
-#[derive(Event)]
-pub struct UpdateViewLayerEvent(pub u32);
+struct Layers {
+    maps: Vec<Entity>,
+    /// The cell drawn in the first map cell of every layer.
+    origin: IVec2,
+}
+impl Layers {
+    fn contains(&self, pos: IVec3) -> bool {
+        let local = pos.truncate() - self.origin;
+        local.cmpge(IVec2::ZERO).all() && local.cmplt(WINDOW_SIZE).all() && pos.z >= 0 && pos.z < self.maps.len() as i32
+    }
+}
+
+/// The centre of `cell` in world space. Map rows run down the screen, so cell y does too.
+pub fn cell_to_world(cell: IVec2) -> Vec2 {
+    vec2((cell.x as f32 + 0.5) * TILE_SIZE, -(cell.y as f32 + 0.5) * TILE_SIZE)
+}
+pub fn world_to_cell(pos: Vec2) -> IVec2 {
+    IVec2::new((pos.x / TILE_SIZE).floor() as i32, (-pos.y / TILE_SIZE).floor() as i32)
+}
+/// The window origin that puts the chunk of `cell` in the middle of the window.
+fn window_origin(cell: IVec2) -> IVec2 {
+    let chunk = CHUNK_SIZE.truncate();
+    (cell.div_euclid(chunk) - IVec2::splat(WINDOW_CHUNKS / 2)) * chunk
+}
+/// Where the map of layer `z` goes for its first cell to show `origin`, maps being centred on their translation.
+fn layer_translation(origin: IVec2, z: usize) -> Vec3 {
+    let centre = cell_to_world(origin + WINDOW_SIZE / 2) - vec2(TILE_SIZE, -TILE_SIZE) / 2.;
+    centre.extend(z as f32)
+}
 #[derive(Resource)]
 pub struct AsciiAtlas(pub(crate) Handle<Image>);
 impl FromWorld for AsciiAtlas {
     fn from_world(world: &mut World) -> Self {
+        let atlas = world.get_resource::<Config>()
+            .map(|config| config.atlas.clone())
+            .unwrap_or_else(|| String::from("atlas.png"));
         Self (
-            world.get_resource::<AssetServer>().unwrap().load("atlas.png")
+            world.get_resource::<AssetServer>().unwrap().load(atlas)
         )
     }
 }
 
+pub(crate) fn terrain_tile(terrain: Terrain) -> (u32, Color, Color) {
+    match terrain {
+        Terrain::Air => (' ' as u32, Color::NONE, Color::NONE),
+        Terrain::Bedrock => ('#' as u32, Color::DARK_GRAY, Color::BLACK),
+        Terrain::Stone => ('#' as u32, Color::GRAY, Color::rgb(0.15, 0.15, 0.15)),
+        Terrain::Dirt => ('%' as u32, Color::rgb(0.55, 0.35, 0.15), Color::rgb(0.2, 0.12, 0.05)),
+        Terrain::Grass => ('"' as u32, Color::GREEN, Color::rgb(0.05, 0.2, 0.05)),
+        Terrain::Water => ('~' as u32, Color::CYAN, Color::rgb(0.05, 0.1, 0.4)),
+        Terrain::StairsUp => ('<' as u32, Color::WHITE, Color::NONE),
+        Terrain::StairsDown => ('>' as u32, Color::WHITE, Color::NONE),
+        Terrain::Ladder => ('H' as u32, Color::rgb(0.7, 0.5, 0.3), Color::NONE),
+        Terrain::Wall => ('#' as u32, Color::rgb(0.75, 0.7, 0.6), Color::rgb(0.3, 0.27, 0.22)),
+        Terrain::Floor => ('=' as u32, Color::rgb(0.7, 0.5, 0.3), Color::rgb(0.3, 0.2, 0.1)),
+        Terrain::Ramp => ('^' as u32, Color::WHITE, Color::NONE),
+    }
+}
+
+/// What a renderer needs to know to draw a cell of the world.
+#[derive(SystemParam)]
+pub(crate) struct CellView<'w, 's> {
+    grid: Res<'w, Grid>,
+    appearances: Query<'w, 's, &'static Appearance>,
+    viewer: Query<'w, 's, (&'static Viewshed, &'static Memory), With<PlayerMarker>>,
+    light: Option<Res<'w, LightMap>>,
+}
+impl<'w, 's> CellView<'w, 's> {
+    /// Everything counts as seen while there is no local player to see it.
+    fn visibility(&self, pos: IVec3) -> CellVisibility {
+        match self.viewer.get_single() {
+            Ok((viewshed, memory)) => memory.visibility(viewshed, pos),
+            Err(_) => CellVisibility::Seen,
+        }
+    }
+    /// The terrain at `pos` with the highest priority occupant drawn over it, occupants
+    /// only while the cell is in view. Occupants without an `Appearance` get the default one.
+    /// Colours are lit by the `LightMap`, the fog state travels to the shader in the foreground alpha.
+    pub(crate) fn tile(&self, pos: IVec3) -> (u32, Color, Color) {
+        let visibility = self.visibility(pos);
+        let (tile, ft_color, bg_color) = terrain_tile(self.grid.terrain(pos).unwrap_or_default());
+        let top = self.grid.occupants(pos).iter()
+            .filter(|_| visibility == CellVisibility::Seen)
+            .map(|e| self.appearances.get(*e).copied().unwrap_or_default())
+            .max_by_key(|a| a.priority);
+        let (tile, ft_color, bg_color) = match top {
+            Some(a) => (a.glyph as u32, a.fg, if a.bg.a() > 0. { a.bg } else { bg_color }),
+            None => (tile, ft_color, bg_color),
+        };
+        let (ft_color, bg_color) = match &self.light {
+            Some(light) => (light.apply(pos, ft_color), light.apply(pos, bg_color)),
+            None => (ft_color, bg_color),
+        };
+        let fog = match visibility {
+            CellVisibility::Seen => 1.,
+            CellVisibility::Remembered => 0.5,
+            CellVisibility::Unknown => 0.,
+        };
+        (tile, ft_color.with_a(fog), bg_color)
+    }
+}
+
 fn startup(
     mut commands: Commands,
 ) {
     commands.spawn(Camera2dBundle::default()).insert(ViewLayer(0));
 }
 
-fn add_layers(
-    mut commands: Commands,
-    ascii_atlas: Res<AsciiAtlas>,
-    mut materials: ResMut<Assets<Map<UserData>>>,
-    mut settings: Res<WorldSettings>,
+fn spawn_layers(
+    commands: &mut Commands,
+    ascii_atlas: &AsciiAtlas,
+    materials: &mut Assets<Map<UserData>>,
+    view: &CellView,
+    origin: IVec2,
 ) {
     let mut layers: Vec<Entity> = Vec::new();
     commands.spawn_empty()
         .with_children(|parent| {
-            for z in 0..settings.size.z {
+            for z in 0..view.grid.depth() as i32 {
                 let map = Map::<UserData>::builder(
-                    settings.size.xy(),
+                    WINDOW_SIZE.as_uvec2(),
                     ascii_atlas.0.clone(),
-                    vec2(16., 16.),
+                    vec2(TILE_SIZE, TILE_SIZE),
                 )
                     .with_user_data(UserData { alpha: 1.})
                     .build_and_initialize(
                         |m| {
-                            for y in 0..m.size().x {
-                                for x in 0..m.size().y {
-                                    m.set(x, y, ' ' as u32, Color::NONE, Color::NONE);
+                            for y in 0..m.size().y {
+                                for x in 0..m.size().x {
+                                    let (tile, ft_color, bg_color) = view.tile((origin + UVec2::new(x, y).as_ivec2()).extend(z));
+                                    m.set(x, y, tile, ft_color, bg_color);
                                 }
                             }
                         }
                     );
                 let child_id = parent.spawn(MapBundleManaged::<UserData> {
                     material: materials.add(map),
-                    transform: Transform::default().with_translation(vec3(0., 0., z as f32)),
+                    transform: Transform::default().with_translation(layer_translation(origin, z as usize)),
                     ..default()
                 }).id();
                 layers.push(child_id);
             }
         })
-        .insert((Layers(layers), InheritedVisibility::VISIBLE, GlobalTransform::default()));
+        .insert((Layers { maps: layers, origin }, InheritedVisibility::VISIBLE, GlobalTransform::default()));
 }
 
-fn add_event_reader(
-    mut add: EventReader<AsciiAddEvent>,
+/// Repaints every cell of the window within the inclusive box between `min` and `max`.
+fn repaint_box(
+    layers: &Layers,
+    min: IVec3,
+    max: IVec3,
+    view: &CellView,
+    materials: &mut Assets<Map<UserData>>,
+    maps: &Query<&Handle<Map<UserData>>>,
+) {
+    let lo = min.truncate().max(layers.origin);
+    let hi = max.truncate().min(layers.origin + WINDOW_SIZE - IVec2::ONE);
+    for z in min.z.max(0)..=max.z.min(layers.maps.len() as i32 - 1) {
+        let map = materials.get_mut(maps.get(layers.maps[z as usize]).unwrap()).unwrap();
+        let mut m = map.indexer_mut();
+        for y in lo.y..=hi.y {
+            for x in lo.x..=hi.x {
+                let local = (IVec2::new(x, y) - layers.origin).as_uvec2();
+                let (tile, ft_color, bg_color) = view.tile(IVec3::new(x, y, z));
+                m.set(local.x, local.y, tile, ft_color, bg_color);
+            }
+        }
+    }
+}
+
+fn add_layers(
+    mut commands: Commands,
+    ascii_atlas: Res<AsciiAtlas>,
     mut materials: ResMut<Assets<Map<UserData>>>,
-    maps: Query<&Handle<Map<UserData>>>,
-    layers: Query<&Layers>
+    view: CellView,
+    camera: Query<&Transform, With<ViewLayer>>,
 ) {
+    let origin = window_origin(camera.get_single().map_or(IVec2::ZERO, |t| world_to_cell(t.translation.truncate())));
+    spawn_layers(&mut commands, &ascii_atlas, &mut materials, &view, origin);
+}
 
-    let layers = layers.single();
-    for ev in add.read() {
-        let pos = ev.pos;
-        let map_handle = maps.get(*layers.0.get(pos.z as usize).unwrap()).unwrap();
-        let map = materials.get_mut(map_handle).unwrap();
-        let mut m = map.indexer_mut();
-        m.set(pos.x, pos.y, '@' as u32, Color::PINK, Color::NONE);
+fn reload_layers(
+    mut commands: Commands,
+    mut loaded: EventReader<WorldLoadedEvent>,
+    ascii_atlas: Res<AsciiAtlas>,
+    mut materials: ResMut<Assets<Map<UserData>>>,
+    view: CellView,
+    layers: Query<Entity, With<Layers>>,
+    camera: Query<&Transform, With<ViewLayer>>,
+) {
+    if loaded.read().last().is_none() {
+        return;
     }
+    for entity in layers.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    let origin = window_origin(camera.get_single().map_or(IVec2::ZERO, |t| world_to_cell(t.translation.truncate())));
+    spawn_layers(&mut commands, &ascii_atlas, &mut materials, &view, origin);
 }
-fn move_event_reader(
+
+/// Moves the window along once the camera entered another chunk.
+fn recycle_layers(
+    camera: Query<&Transform, With<ViewLayer>>,
+    mut layers: Query<&mut Layers>,
+    mut transforms: Query<&mut Transform, Without<ViewLayer>>,
+    view: CellView,
+    mut materials: ResMut<Assets<Map<UserData>>>,
+    maps: Query<&Handle<Map<UserData>>>,
+) {
+    let (Ok(camera), Ok(mut layers)) = (camera.get_single(), layers.get_single_mut()) else {
+        return;
+    };
+    let origin = window_origin(world_to_cell(camera.translation.truncate()));
+    if origin == layers.origin {
+        return;
+    }
+    layers.origin = origin;
+    for (z, layer) in layers.maps.iter().enumerate() {
+        if let Ok(mut transform) = transforms.get_mut(*layer) {
+            transform.translation = layer_translation(origin, z);
+        }
+    }
+    let max = (origin + WINDOW_SIZE - IVec2::ONE).extend(layers.maps.len() as i32 - 1);
+    repaint_box(&layers, origin.extend(0), max, &view, &mut materials, &maps);
+}
+
+/// Redraws every cell something entered, left, was removed from or changed its looks in,
+/// every reshaped cell, every cell that came into or went out of the player's view and
+/// every cell lit differently and every chunk that loaded or unloaded.
+/// A new view repaints everything, since the layers were drawn without fog.
+fn repaint_cells(
+    mut add: EventReader<AsciiAddEvent>,
     mut mov: EventReader<AsciiMoveEvent>,
+    mut remove: EventReader<AsciiRemoveEvent>,
+    mut terrain: EventReader<TerrainChangedEvent>,
+    mut chunks: EventReader<ChunkEvent>,
+    changed: Query<&AsciiTile, Changed<Appearance>>,
+    viewer: Query<Ref<Viewshed>, With<PlayerMarker>>,
+    mut in_view: Local<HashSet<IVec3>>,
+    view: CellView,
     mut materials: ResMut<Assets<Map<UserData>>>,
     maps: Query<&Handle<Map<UserData>>>,
     layers: Query<&Layers>
 ) {
-
-    let layers = layers.single();
-    for ev in mov.read() {
-        {
-            let old_pos = ev.old_pos;
-            let map_handle = maps.get(*layers.0.get(old_pos.z as usize).unwrap()).unwrap();
-            let map = materials.get_mut(map_handle).unwrap();
-            let mut m = map.indexer_mut();
-            m.set(old_pos.x, old_pos.y, ' ' as u32, Color::NONE, Color::NONE);
+    let mut dirty = Vec::new();
+    dirty.extend(add.read().map(|ev| ev.pos));
+    dirty.extend(mov.read().flat_map(|ev| [ev.old_pos, ev.new_pos]));
+    dirty.extend(changed.iter().map(|tile| tile.pos));
+    dirty.extend(remove.read().map(|ev| ev.pos));
+    dirty.extend(terrain.read().map(|ev| ev.pos));
+    let streamed = chunks.read()
+        .map(|ev| match *ev {
+            ChunkEvent::Loaded(chunk) | ChunkEvent::Unloaded(chunk) => chunk,
+        })
+        .collect::<Vec<_>>();
+    let mut repaint_all = false;
+    if let Some(light) = &view.light {
+        dirty.extend(light.dirty.iter().copied());
+    }
+    if let Ok(viewshed) = viewer.get_single() {
+        if viewshed.is_added() {
+            repaint_all = true;
+        } else if viewshed.is_changed() {
+            dirty.extend(in_view.symmetric_difference(&viewshed.visible).copied());
         }
-        {
-            let new_pos = ev.new_pos;
-            let map_handle = maps.get(*layers.0.get(new_pos.z as usize).unwrap()).unwrap();
-            let map = materials.get_mut(map_handle).unwrap();
-            let mut m = map.indexer_mut();
-            m.set(new_pos.x, new_pos.y, '@' as u32, Color::PINK, Color::NONE);
+        if viewshed.is_changed() {
+            *in_view = viewshed.visible.clone();
         }
     }
+    let Ok(layers) = layers.get_single() else {
+        return;
+    };
+    if repaint_all {
+        let max = (layers.origin + WINDOW_SIZE - IVec2::ONE).extend(layers.maps.len() as i32 - 1);
+        repaint_box(layers, layers.origin.extend(0), max, &view, &mut materials, &maps);
+        return;
+    }
+    for chunk in streamed {
+        let min = chunk * CHUNK_SIZE;
+        repaint_box(layers, min, min + CHUNK_SIZE - IVec3::ONE, &view, &mut materials, &maps);
+    }
+    for pos in dirty.into_iter().filter(|pos| layers.contains(*pos)) {
+        let map_handle = maps.get(layers.maps[pos.z as usize]).unwrap();
+        let map = materials.get_mut(map_handle).unwrap();
+        let mut m = map.indexer_mut();
+        let local = (pos.truncate() - layers.origin).as_uvec2();
+        let (tile, ft_color, bg_color) = view.tile(pos);
+        m.set(local.x, local.y, tile, ft_color, bg_color);
+    }
 }
 
 fn camera_control(
-    key: Res<ButtonInput<KeyCode>>,
-    mouse_button: Res<ButtonInput<MouseButton>>,
+    actions: Res<ActionState>,
     settings: Res<WorldSettings>,
     mut mouse_motion_events: EventReader<MouseMotion>,
     mut mouse_wheel_events: EventReader<MouseWheel>,
@@ -124,9 +331,9 @@ fn camera_control(
     )>,
     mut update_view_layer: EventWriter<UpdateViewLayerEvent>,
 ) {
-    if key.pressed(KeyCode::ControlLeft) {
+    if actions.pressed(Action::CameraModifier) {
         for event in mouse_motion_events.read() {
-            if mouse_button.pressed(MouseButton::Left) || mouse_button.pressed(MouseButton::Right) {
+            if actions.pressed(Action::CameraDrag) {
                 for (_, mut transform, _, _, _) in camera_query.iter_mut() {
                     transform.translation.x -= event.delta.x * transform.scale.x;
                     transform.translation.y += event.delta.y * transform.scale.y;
@@ -154,7 +361,7 @@ fn camera_control(
                 wheel_y += event.y;
             }
             wheel_y = wheel_y.floor();
-            if wheel_y >= 1. && view.0 < settings.size.z - 1 {
+            if wheel_y >= 1. && view.0 < settings.depth - 1 {
                 view.0 += 1;
             } else if wheel_y <= -1. && view.0 > 0{
                 view.0 -= 1;
@@ -164,8 +371,30 @@ fn camera_control(
     }
 }
 
+/// Centres the camera on the player whenever they move, dragging it away lasts until then.
+fn follow_player(
+    config: Option<Res<Config>>,
+    player: Query<&AsciiTile, (With<PlayerMarker>, Changed<AsciiTile>)>,
+    mut view: Query<(&mut ViewLayer, &mut Transform)>,
+    mut update_view_layer: EventWriter<UpdateViewLayerEvent>,
+) {
+    if config.is_some_and(|config| !config.follow_player) {
+        return;
+    }
+    if let Ok(tile) = player.get_single() {
+        for (mut view, mut transform) in view.iter_mut() {
+            let centre = cell_to_world(tile.pos.truncate());
+            transform.translation.x = centre.x;
+            transform.translation.y = centre.y;
+            if view.0 != tile.pos.z as u32 {
+                view.0 = tile.pos.z as u32;
+                update_view_layer.send(UpdateViewLayerEvent(view.0));
+            }
+        }
+    }
+}
+
 fn update_visibility(
-    settings: Res<WorldSettings>,
     mut commands: Commands,
     mut update: EventReader<UpdateViewLayerEvent>,
     mut materials: ResMut<Assets<Map<UserData>>>,
@@ -174,10 +403,10 @@ fn update_visibility(
 ) {
     if let Ok(layers) = layers.get_single() {
         for ev in update.read() {
-            for i in  0..settings.size.z {
-                let map_handle = maps.get(*layers.0.get(i as usize).unwrap()).unwrap();
+            for i in  0..layers.maps.len() as u32 {
+                let map_handle = maps.get(*layers.maps.get(i as usize).unwrap()).unwrap();
                 let mut map = materials.get_mut(map_handle).unwrap();
-                let mut layer = commands.entity(*layers.0.get(i as usize).unwrap());
+                let mut layer = commands.entity(*layers.maps.get(i as usize).unwrap());
                 if i <= ev.0 {
                     map.user_data.alpha = (i + 5) as f32 / (ev.0 + 5) as f32 ;
                 } else {
@@ -198,14 +427,12 @@ pub struct AsciiRenderPlugin;
 impl Plugin for AsciiRenderPlugin {
     fn build(&self, app: &mut App) {
         app
-            .add_event::<UpdateViewLayerEvent>()
             .add_systems(Startup, startup)
-            .add_systems(Update, (
-                add_event_reader,
-                move_event_reader
-                ).run_if(in_state(MainState::InGame)))
+            .add_systems(OnEnter(MainState::InGame), add_layers)
+            .add_systems(Update, repaint_cells.after(AsciiWorldSet).after(FovSet).after(LightingSet).run_if(in_state(MainState::InGame)))
             .add_systems(Update, camera_control)
-            .add_systems(Update, update_visibility.run_if(in_state(MainState::InGame)))
+            .add_systems(Update, (follow_player, update_visibility).chain().run_if(in_state(MainState::InGame)))
+            .add_systems(Update, (reload_layers, recycle_layers).chain().after(follow_player).before(repaint_cells).run_if(in_state(MainState::InGame)))
             .add_plugins(CustomFastTileMapPlugin::<UserData> {
                 user_code: Some(
                     r#"
@@ -219,6 +446,13 @@ impl Plugin for AsciiRenderPlugin {
                         var tile_ft_color = get_tile_ft_color(tile_position);
                         var tile_bg_color = get_tile_bg_color(tile_position);
 
+                        // The foreground alpha carries the fog state:
+                        // 1 seen, 0.5 remembered, 0 never seen.
+                        var fog = tile_ft_color.a;
+                        if fog < 0.25 {
+                            return vec4<f32>(0.0, 0.0, 0.0, 0.0);
+                        }
+
                         var tile_start = atlas_index_to_position(tile_index);
                         // Offset in pixels from tile_start to sample from
                         var rect_offset = floor(tile_offset) + map.tile_anchor_point * map.tile_size;
@@ -245,7 +479,12 @@ impl Plugin for AsciiRenderPlugin {
                         if color.a == 0.0 {
                             color = tile_bg_color;
                         } else {
-                            color *= tile_ft_color;
+                            color *= vec4<f32>(tile_ft_color.rgb, 1.0);
+                        }
+                        if fog < 0.75 {
+                            // Remembered cells are drawn dim and washed out.
+                            var grey = dot(color.rgb, vec3<f32>(0.3, 0.59, 0.11));
+                            color = vec4<f32>(mix(color.rgb, vec3<f32>(grey), 0.6) * 0.4, color.a);
                         }
                         color *= vec4<f32>(1.0, 1.0, 1.0, user_data.alpha);
                         return color;
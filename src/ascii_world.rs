@@ -1,61 +1,553 @@
+use bevy::ecs::system::SystemParam;
 use bevy::prelude::*;
+use std::sync::atomic::{AtomicU64, Ordering};
+use bevy::utils::HashMap;
+use serde::{Deserialize, Serialize};
+use crate::chunk::{chunk_of, Chunk, CHUNK_SIZE};
+use crate::turn::TimeMode;
 
 #[derive(Component)]
 pub struct AsciiTile {
-    pub pos: UVec3,
+    pub pos: IVec3,
 }
 
 #[derive(Resource)]
 pub struct WorldSettings {
-    pub size: UVec3
+    /// Layers of the world, a multiple of the chunk height.
+    pub depth: u32,
+    pub time_mode: TimeMode
 }
 impl FromWorld for WorldSettings {
     fn from_world(_world: &mut World) -> Self {
         Self {
-            size: UVec3::new(64, 64, 64)
+            depth: 64,
+            time_mode: TimeMode::default()
         }
     }
 }
 
+/// The layer a view shows, kept on the camera. Lives with the world rather than the
+/// renderer so saves carry it even when nothing is drawn.
+#[derive(Component)]
+pub struct ViewLayer(pub u32);
+#[derive(Event)]
+pub struct UpdateViewLayerEvent(pub u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub enum Terrain {
+    #[default]
+    Air,
+    Bedrock,
+    Stone,
+    Dirt,
+    Grass,
+    Water,
+    StairsUp,
+    StairsDown,
+    Ladder,
+    /// Built from rocks, see `terraform::Structure`.
+    Wall,
+    /// Built from sticks to stand on.
+    Floor,
+    /// Climbs up like `StairsUp`, but takes no longer than walking.
+    Ramp,
+}
+impl Terrain {
+    pub fn is_solid(&self) -> bool {
+        !matches!(self, Terrain::Air | Terrain::Water | Terrain::StairsUp | Terrain::StairsDown | Terrain::Ladder | Terrain::Ramp)
+    }
+    /// Whether something in the cell directly above can stand on this terrain.
+    pub fn supports_above(&self) -> bool {
+        self.is_solid() || self.climbs_up()
+    }
+    /// Whether standing on this terrain lets you climb to the layer above.
+    pub fn climbs_up(&self) -> bool {
+        matches!(self, Terrain::StairsUp | Terrain::Ladder | Terrain::Ramp)
+    }
+    /// Whether standing on this terrain lets you climb to the layer below.
+    pub fn climbs_down(&self) -> bool {
+        matches!(self, Terrain::StairsDown | Terrain::Ladder)
+    }
+    /// Cost of walking or climbing into a cell of this terrain.
+    pub fn move_cost(&self) -> u32 {
+        match self {
+            Terrain::Water => 3,
+            Terrain::StairsUp | Terrain::StairsDown | Terrain::Ladder => 2,
+            _ => 1,
+        }
+    }
+}
+
+/// Shared by every grid, so a revision is never repeated when the grid is replaced.
+static REVISION: AtomicU64 = AtomicU64::new(0);
+fn next_revision() -> u64 {
+    REVISION.fetch_add(1, Ordering::Relaxed) + 1
+}
+
+pub struct Cell<'a> {
+    pub terrain: Terrain,
+    pub occupants: &'a [Entity]
+}
+
+/// Authoritative record of what is at every position of the world.
+/// Terrain is stored in the chunks loaded right now, see `chunk::stream_chunks`, anything
+/// outside them counts as out of bounds. Entities are indexed by the position of their `AsciiTile`.
+#[derive(Resource)]
+pub struct Grid {
+    depth: u32,
+    chunks: HashMap<IVec3, Chunk>,
+    occupants: HashMap<IVec3, Vec<Entity>>,
+    positions: HashMap<Entity, IVec3>,
+    /// When the grid was created, the floor for every chunk's revision.
+    revision: u64,
+    /// When each chunk last changed its terrain, loaded or unloaded.
+    revisions: HashMap<IVec3, u64>,
+}
+impl FromWorld for Grid {
+    fn from_world(world: &mut World) -> Self {
+        world.init_resource::<WorldSettings>();
+        Self::new(world.resource::<WorldSettings>().depth)
+    }
+}
+impl Grid {
+    pub fn new(depth: u32) -> Self {
+        Self {
+            depth,
+            chunks: HashMap::new(),
+            occupants: HashMap::new(),
+            positions: HashMap::new(),
+            revision: next_revision(),
+            revisions: HashMap::new(),
+        }
+    }
+    /// Number of layers. Positions run from 0 to `depth - 1` vertically and are unbounded horizontally.
+    pub fn depth(&self) -> u32 {
+        self.depth
+    }
+    pub fn contains(&self, pos: IVec3) -> bool {
+        self.locate(pos).is_some()
+    }
+    /// The loaded chunk holding `pos` and where in it `pos` is.
+    fn locate(&self, pos: IVec3) -> Option<(&Chunk, IVec3)> {
+        if pos.z < 0 || pos.z >= self.depth as i32 {
+            return None;
+        }
+        let chunk = chunk_of(pos);
+        self.chunks.get(&chunk).map(|c| (c, pos - chunk * CHUNK_SIZE))
+    }
+    pub fn get(&self, pos: IVec3) -> Option<Cell<'_>> {
+        self.terrain(pos).map(|terrain| Cell {
+            terrain,
+            occupants: self.occupants(pos)
+        })
+    }
+    pub fn terrain(&self, pos: IVec3) -> Option<Terrain> {
+        self.locate(pos).map(|(chunk, local)| chunk.get(local))
+    }
+    /// Changes whenever terrain changes in the chunks overlapping the inclusive box between
+    /// `min` and `max`, or one of them comes or goes. Caches derived from terrain compare
+    /// against it, so streaming elsewhere leaves them be.
+    pub fn revision_in(&self, min: IVec3, max: IVec3) -> u64 {
+        let top = self.depth as i32 - 1;
+        let (min, max) = (chunk_of(min.with_z(min.z.clamp(0, top))), chunk_of(max.with_z(max.z.clamp(0, top))));
+        (min.z..=max.z).flat_map(|z| (min.y..=max.y).flat_map(move |y| (min.x..=max.x).map(move |x| IVec3::new(x, y, z))))
+            .filter_map(|chunk| self.revisions.get(&chunk).copied())
+            .fold(self.revision, u64::max)
+    }
+    pub fn set_terrain(&mut self, pos: IVec3, terrain: Terrain) -> bool {
+        if pos.z < 0 || pos.z >= self.depth as i32 {
+            return false;
+        }
+        let chunk_pos = chunk_of(pos);
+        let Some(chunk) = self.chunks.get_mut(&chunk_pos) else {
+            return false;
+        };
+        if chunk.set(pos - chunk_pos * CHUNK_SIZE, terrain) {
+            chunk.modified = true;
+            self.revisions.insert(chunk_pos, next_revision());
+        }
+        true
+    }
+    pub fn is_loaded(&self, chunk: IVec3) -> bool {
+        self.chunks.contains_key(&chunk)
+    }
+    pub fn chunks(&self) -> impl Iterator<Item = (IVec3, &Chunk)> + '_ {
+        self.chunks.iter().map(|(pos, chunk)| (*pos, chunk))
+    }
+    pub(crate) fn insert_chunk(&mut self, pos: IVec3, chunk: Chunk) {
+        self.chunks.insert(pos, chunk);
+        self.revisions.insert(pos, next_revision());
+    }
+    pub(crate) fn remove_chunk(&mut self, pos: IVec3) -> Option<Chunk> {
+        let chunk = self.chunks.remove(&pos)?;
+        self.revisions.insert(pos, next_revision());
+        Some(chunk)
+    }
+    /// Entities on the grid within the chunk at `chunk`, loaded or not.
+    pub fn entities_in(&self, chunk: IVec3) -> Vec<Entity> {
+        self.positions.iter()
+            .filter(|(_, pos)| chunk_of(**pos) == chunk)
+            .map(|(entity, _)| *entity)
+            .collect()
+    }
+    /// The first position above the highest solid or water cell of the column at `x`, `y`,
+    /// as far as the column is loaded.
+    pub fn surface(&self, x: i32, y: i32) -> Option<IVec3> {
+        let top = self.depth as i32 - 1;
+        (0..=top).rev()
+            .find(|z| self.terrain(IVec3::new(x, y, *z)).is_some_and(|t| t.is_solid() || t == Terrain::Water))
+            .map(|z| IVec3::new(x, y, (z + 1).min(top)))
+    }
+    /// Whether something at `pos` stays put instead of falling.
+    pub fn supported(&self, pos: IVec3) -> bool {
+        if pos.z == 0 {
+            return true;
+        }
+        let here = self.terrain(pos).unwrap_or_default();
+        let below = self.terrain(pos - IVec3::Z).unwrap_or_default();
+        here == Terrain::Water || here.climbs_up() || here.climbs_down() || below.supports_above()
+    }
+    pub fn occupants(&self, pos: IVec3) -> &[Entity] {
+        self.occupants.get(&pos).map(|v| v.as_slice()).unwrap_or(&[])
+    }
+    pub fn is_occupied(&self, pos: IVec3) -> bool {
+        !self.occupants(pos).is_empty()
+    }
+    pub fn position_of(&self, entity: Entity) -> Option<IVec3> {
+        self.positions.get(&entity).copied()
+    }
+    /// The loaded cells of the 3x3x3 block around `pos`, excluding `pos` itself.
+    pub fn neighbours(&self, pos: IVec3) -> impl Iterator<Item = IVec3> + '_ {
+        (-1..=1).flat_map(move |z| (-1..=1).flat_map(move |y| (-1..=1).map(move |x| IVec3::new(x, y, z))))
+            .filter(|d| *d != IVec3::ZERO)
+            .map(move |d| pos + d)
+            .filter(|p| self.contains(*p))
+    }
+    /// Every loaded cell in the inclusive box between `min` and `max`.
+    pub fn iter_box(&self, min: IVec3, max: IVec3) -> impl Iterator<Item = (IVec3, Cell<'_>)> + '_ {
+        let (min_z, max_z) = (min.z.max(0), max.z.min(self.depth as i32 - 1));
+        (min_z..=max_z).flat_map(move |z| (min.y..=max.y).flat_map(move |y| (min.x..=max.x).map(move |x| IVec3::new(x, y, z))))
+            .filter_map(|p| self.get(p).map(|cell| (p, cell)))
+    }
+    pub(crate) fn insert(&mut self, entity: Entity, pos: IVec3) {
+        self.remove(entity);
+        self.occupants.entry(pos).or_default().push(entity);
+        self.positions.insert(entity, pos);
+    }
+    pub(crate) fn remove(&mut self, entity: Entity) -> Option<IVec3> {
+        let pos = self.positions.remove(&entity)?;
+        if let Some(list) = self.occupants.get_mut(&pos) {
+            list.retain(|e| *e != entity);
+            if list.is_empty() {
+                self.occupants.remove(&pos);
+            }
+        }
+        Some(pos)
+    }
+}
+
+/// Entities that nothing else can move into.
+#[derive(Component)]
+pub struct Blocking;
+/// Entities that fall when nothing supports them.
+#[derive(Component)]
+pub struct Gravity;
+/// How an `AsciiTile` entity is drawn. Where several entities share a cell the highest
+/// `priority` is shown, a transparent `bg` lets the terrain background show through.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Appearance {
+    pub glyph: char,
+    pub fg: Color,
+    pub bg: Color,
+    pub priority: i32
+}
+impl Default for Appearance {
+    fn default() -> Self {
+        Self {
+            glyph: '@',
+            fg: Color::PINK,
+            bg: Color::NONE,
+            priority: 0
+        }
+    }
+}
+/// Closed doors block movement, bumping into one opens it.
+#[derive(Component)]
+pub struct Door {
+    pub open: bool
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockReason {
+    OutOfBounds,
+    Terrain(Terrain),
+    Entity(Entity),
+    ClosedDoor(Entity),
+    NoStairs,
+}
+
+/// Systems keeping the `Grid` in sync with tile events, views should run after it.
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AsciiWorldSet;
+
 #[derive(Event)]
 pub struct AsciiAddEvent {
     pub entity: Entity,
-    pub pos: UVec3
+    pub pos: IVec3
+}
+/// Takes an entity that was despawned or lost its `AsciiTile` off the grid at `pos`, where it
+/// was last. Sent automatically for entities still on the grid, systems that need the grid
+/// updated within the same tick take them off themselves and send this directly.
+#[derive(Event, Debug, Clone, Copy)]
+pub struct AsciiRemoveEvent {
+    pub entity: Entity,
+    pub pos: IVec3
 }
+impl AsciiRemoveEvent {
+    pub fn new(entity: Entity, pos: IVec3) -> Self {
+        Self {
+            entity,
+            pos
+        }
+    }
+}
+#[derive(Event, Debug, Clone, Copy)]
+pub struct AsciiMoveEvent {
+    pub entity: Entity,
+    pub old_pos: IVec3,
+    pub new_pos: IVec3
+}
+
 #[derive(Event)]
-pub struct AsciiRemoveEvent(Entity);
+pub struct MoveRejectedEvent {
+    pub entity: Entity,
+    pub pos: IVec3,
+    pub reason: BlockReason
+}
+
+/// Sent by whatever reshapes the world after the game started, so views of the terrain
+/// can update the cell. Caches derived from terrain follow `Grid::revision_in` instead.
+#[derive(Event, Debug, Clone, Copy)]
+pub struct TerrainChangedEvent {
+    pub pos: IVec3,
+    pub old: Terrain,
+    pub new: Terrain
+}
+
+/// Hook for fall damage, sent after an entity has dropped `distance` layers.
 #[derive(Event)]
-pub struct AsciiMoveEvent {
+pub struct FallEvent {
     pub entity: Entity,
-    pub old_pos: UVec3,
-    pub new_pos: UVec3
+    pub from: IVec3,
+    pub to: IVec3,
+    pub distance: u32
+}
+
+/// Moves `AsciiTile` entities on the grid, refusing moves into anything impassable.
+/// Shared by everything that moves, so the grid stays consistent within a frame.
+#[derive(SystemParam)]
+pub struct GridMover<'w, 's> {
+    grid: ResMut<'w, Grid>,
+    tiles: Query<'w, 's, &'static mut AsciiTile>,
+    blocking: Query<'w, 's, (), With<Blocking>>,
+    doors: Query<'w, 's, &'static mut Door>,
+    moved: EventWriter<'w, AsciiMoveEvent>,
+    rejected: EventWriter<'w, MoveRejectedEvent>,
+}
+impl<'w, 's> GridMover<'w, 's> {
+    pub fn grid(&self) -> &Grid {
+        &self.grid
+    }
+    pub fn passable(&self, pos: IVec3) -> Result<(), BlockReason> {
+        let Some(cell) = self.grid.get(pos) else {
+            return Err(BlockReason::OutOfBounds);
+        };
+        if cell.terrain.is_solid() {
+            return Err(BlockReason::Terrain(cell.terrain));
+        }
+        for occupant in cell.occupants {
+            if let Ok(door) = self.doors.get(*occupant) {
+                if !door.open {
+                    return Err(BlockReason::ClosedDoor(*occupant));
+                }
+            } else if self.blocking.contains(*occupant) {
+                return Err(BlockReason::Entity(*occupant));
+            }
+        }
+        Ok(())
+    }
+    /// Climbs one layer up or down, which needs stairs or a ladder to climb on.
+    pub fn try_climb(&mut self, entity: Entity, up: bool) -> Result<IVec3, BlockReason> {
+        let Ok(pos) = self.tiles.get(entity).map(|tile| tile.pos) else {
+            return Err(BlockReason::OutOfBounds);
+        };
+        let here = self.grid.terrain(pos).unwrap_or_default();
+        let can_climb = if up {
+            here.climbs_up()
+        } else {
+            let below = if pos.z > 0 { self.grid.terrain(pos - IVec3::Z).unwrap_or_default() } else { Terrain::Bedrock };
+            here.climbs_down() || below.climbs_up()
+        };
+        if !can_climb {
+            self.rejected.send(MoveRejectedEvent {
+                entity,
+                pos,
+                reason: BlockReason::NoStairs
+            });
+            return Err(BlockReason::NoStairs);
+        }
+        self.try_move(entity, if up { IVec3::Z } else { IVec3::NEG_Z })
+    }
+    /// Drops an unsupported entity to the next layer that supports it.
+    pub fn fall(&mut self, entity: Entity) -> Option<FallEvent> {
+        let from = self.tiles.get(entity).ok()?.pos;
+        let mut to = from;
+        while !self.grid.supported(to) && self.passable(to - IVec3::Z).is_ok() {
+            to -= IVec3::Z;
+        }
+        if to == from {
+            return None;
+        }
+        self.tiles.get_mut(entity).unwrap().pos = to;
+        self.grid.insert(entity, to);
+        self.moved.send(AsciiMoveEvent {
+            entity,
+            old_pos: from,
+            new_pos: to
+        });
+        Some(FallEvent {
+            entity,
+            from,
+            to,
+            distance: (from.z - to.z) as u32
+        })
+    }
+    pub fn try_move(&mut self, entity: Entity, delta: IVec3) -> Result<IVec3, BlockReason> {
+        let Ok(old_pos) = self.tiles.get(entity).map(|tile| tile.pos) else {
+            return Err(BlockReason::OutOfBounds);
+        };
+        let new_pos = old_pos + delta;
+        if let Err(reason) = self.passable(new_pos) {
+            if let BlockReason::ClosedDoor(door) = reason {
+                if let Ok(mut door) = self.doors.get_mut(door) {
+                    door.open = true;
+                }
+            }
+            self.rejected.send(MoveRejectedEvent {
+                entity,
+                pos: new_pos,
+                reason
+            });
+            return Err(reason);
+        }
+        if new_pos != old_pos {
+            self.tiles.get_mut(entity).unwrap().pos = new_pos;
+            self.grid.insert(entity, new_pos);
+            self.moved.send(AsciiMoveEvent {
+                entity,
+                old_pos,
+                new_pos
+            });
+        }
+        Ok(new_pos)
+    }
 }
 
 fn startup(
     mut event: EventWriter<AsciiAddEvent>,
     mut commands: Commands
 ) {
-    let pos = UVec3::default();
+    let pos = IVec3::default();
     let entity = commands.spawn(AsciiTile {pos}).id();
     event.send(AsciiAddEvent {
         entity,
         pos
     });
-    let pos = UVec3::new(1, 1, 1);
+    let pos = IVec3::new(1, 1, 1);
     let entity = commands.spawn(AsciiTile {pos}).id();
     event.send(AsciiAddEvent {
         entity,
         pos
     });
 }
+
+fn apply_gravity(
+    mut mover: GridMover,
+    falling: Query<Entity, (With<Gravity>, With<AsciiTile>)>,
+    mut fall: EventWriter<FallEvent>,
+) {
+    for entity in falling.iter() {
+        if let Some(ev) = mover.fall(entity) {
+            fall.send(ev);
+        }
+    }
+}
+
+fn add_event_reader(
+    mut add: EventReader<AsciiAddEvent>,
+    mut grid: ResMut<Grid>
+) {
+    for ev in add.read() {
+        grid.insert(ev.entity, ev.pos);
+    }
+}
+fn move_event_reader(
+    mut mov: EventReader<AsciiMoveEvent>,
+    mut grid: ResMut<Grid>
+) {
+    for ev in mov.read() {
+        grid.insert(ev.entity, ev.new_pos);
+    }
+}
+/// Sends `AsciiRemoveEvent`s for entities that left the world without one.
+fn emit_removals(
+    mut removed: RemovedComponents<AsciiTile>,
+    grid: Res<Grid>,
+    tiles: Query<(), With<AsciiTile>>,
+    mut remove: EventWriter<AsciiRemoveEvent>
+) {
+    for entity in removed.read() {
+        if tiles.contains(entity) {
+            continue;
+        }
+        if let Some(pos) = grid.position_of(entity) {
+            remove.send(AsciiRemoveEvent::new(entity, pos));
+        }
+    }
+}
+fn remove_event_reader(
+    mut remove: EventReader<AsciiRemoveEvent>,
+    mut grid: ResMut<Grid>,
+    tiles: Query<(), With<AsciiTile>>
+) {
+    for ev in remove.read() {
+        // Entities given a tile again since, like a stack dropped right after pickup, stay.
+        if !tiles.contains(ev.entity) {
+            grid.remove(ev.entity);
+        }
+    }
+}
+
 pub struct AsciiWorldPlugin;
 impl Plugin for AsciiWorldPlugin {
     fn build(&self, app: &mut App) {
         app
             .init_resource::<WorldSettings>()
+            .init_resource::<Grid>()
             // .add_systems(Startup, startup)
             .add_event::<AsciiAddEvent>()
             .add_event::<AsciiRemoveEvent>()
-            .add_event::<AsciiMoveEvent>();
+            .add_event::<AsciiMoveEvent>()
+            .add_event::<MoveRejectedEvent>()
+            .add_event::<FallEvent>()
+            .add_event::<TerrainChangedEvent>()
+            .add_event::<UpdateViewLayerEvent>()
+            .add_systems(Update, (
+                add_event_reader,
+                move_event_reader,
+                emit_removals,
+                remove_event_reader,
+                apply_gravity
+            ).chain().in_set(AsciiWorldSet));
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,219 @@
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use crate::ascii_world::{AsciiAddEvent, AsciiTile, AsciiWorldSet, Grid, Terrain};
+use crate::combat::Projectile;
+use crate::item::scatter_items;
+use crate::living_entity::populate;
+use crate::net::NetMode;
+use crate::player::PlayerMarker;
+use crate::save::{spawn_saved_tile, SavedTile, TileSaver};
+use crate::world_map::{generate_chunk, WorldGen};
+
+/// Size of the pieces the world is generated, loaded and unloaded in.
+pub const CHUNK_SIZE: IVec3 = IVec3::new(32, 32, 16);
+const CHUNK_CELLS: usize = (CHUNK_SIZE.x * CHUNK_SIZE.y * CHUNK_SIZE.z) as usize;
+/// Chunks at most this many chunks away from a player are loaded.
+pub const LOAD_RADIUS: i32 = 2;
+/// Loaded chunks farther than this from every player are unloaded again. Larger than
+/// `LOAD_RADIUS`, so walking back and forth over a chunk border doesn't keep reloading.
+pub const UNLOAD_RADIUS: i32 = 3;
+/// Chunks loaded per frame, nearest first, so walking into new land never stalls a frame.
+const LOADS_PER_FRAME: usize = 4;
+
+/// The chunk `pos` lies in.
+pub fn chunk_of(pos: IVec3) -> IVec3 {
+    pos.div_euclid(CHUNK_SIZE)
+}
+
+/// Terrain of one chunk, indexed by position relative to the chunk's first cell.
+#[derive(Clone)]
+pub struct Chunk {
+    terrain: Vec<Terrain>,
+    /// Set once the terrain changed after generating it. Only those chunks are kept when
+    /// they unload, the rest is generated again from the seed.
+    pub modified: bool,
+}
+impl Default for Chunk {
+    fn default() -> Self {
+        Self {
+            terrain: vec![Terrain::Air; CHUNK_CELLS],
+            modified: false
+        }
+    }
+}
+impl Chunk {
+    fn index(local: IVec3) -> usize {
+        ((local.z * CHUNK_SIZE.y + local.y) * CHUNK_SIZE.x + local.x) as usize
+    }
+    pub fn get(&self, local: IVec3) -> Terrain {
+        self.terrain[Self::index(local)]
+    }
+    /// Whether the terrain changed.
+    pub fn set(&mut self, local: IVec3, terrain: Terrain) -> bool {
+        let cell = &mut self.terrain[Self::index(local)];
+        let changed = *cell != terrain;
+        *cell = terrain;
+        changed
+    }
+    /// Run-length encoded terrain in chunk order (x, then y, then z).
+    pub fn encode(&self) -> Vec<(Terrain, u32)> {
+        let mut runs: Vec<(Terrain, u32)> = Vec::new();
+        for terrain in self.terrain.iter() {
+            match runs.last_mut() {
+                Some((t, n)) if t == terrain => *n += 1,
+                _ => runs.push((*terrain, 1)),
+            }
+        }
+        runs
+    }
+    /// The other way round of `encode`, `None` unless the runs fill exactly one chunk.
+    pub fn decode(runs: &[(Terrain, u32)]) -> Option<Self> {
+        if runs.iter().map(|(_, n)| *n as usize).sum::<usize>() != CHUNK_CELLS {
+            return None;
+        }
+        Some(Self {
+            terrain: runs.iter().flat_map(|(t, n)| std::iter::repeat(*t).take(*n as usize)).collect(),
+            modified: true
+        })
+    }
+}
+
+/// What is left of an unloaded chunk: its terrain if it was reshaped, and whatever stood in it.
+#[derive(Default)]
+pub struct StoredChunk {
+    pub terrain: Option<Chunk>,
+    pub tiles: Vec<SavedTile>,
+}
+
+/// Every chunk that was loaded before and isn't now. Chunks missing from both the store
+/// and the grid were never visited, they get generated and populated when first loaded.
+#[derive(Resource, Default)]
+pub struct ChunkStore {
+    pub chunks: HashMap<IVec3, StoredChunk>,
+}
+impl ChunkStore {
+    /// Reshapes a cell of a chunk that isn't loaded, generating the chunk first if it never was.
+    pub fn set_terrain(&mut self, gen: &WorldGen, depth: u32, pos: IVec3, terrain: Terrain) {
+        let chunk_pos = chunk_of(pos);
+        let chunk = self.chunks.entry(chunk_pos).or_default().terrain
+            .get_or_insert_with(|| generate_chunk(gen, depth, chunk_pos));
+        chunk.set(pos - chunk_pos * CHUNK_SIZE, terrain);
+        chunk.modified = true;
+    }
+}
+
+#[derive(Event, Debug, Clone, Copy)]
+pub enum ChunkEvent {
+    Loaded(IVec3),
+    Unloaded(IVec3),
+}
+
+/// Runs before the grid takes in this frame's tile events, so entities spawned with a
+/// chunk are on the grid in the same frame.
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ChunkSet;
+
+/// What lives in a chunk is decided by the seed and where the chunk is, like its terrain,
+/// not by the order chunks happen to load in.
+fn population_rng(gen: &WorldGen, chunk: IVec3) -> StdRng {
+    let mut seed = [0; 32];
+    for (bytes, n) in seed.chunks_mut(4).zip([gen.seed as i32, chunk.x, chunk.y, chunk.z]) {
+        bytes.copy_from_slice(&n.to_le_bytes());
+    }
+    StdRng::from_seed(seed)
+}
+
+/// Keeps the chunks around every player loaded and unloads the rest. Unloading stores the
+/// entities standing in a chunk and despawns them, loading brings them back. Clients only
+/// stream terrain, their entities come and go with the server's.
+fn stream_chunks(
+    mut commands: Commands,
+    mut grid: ResMut<Grid>,
+    mut store: ResMut<ChunkStore>,
+    gen: Res<WorldGen>,
+    mode: Res<NetMode>,
+    players: Query<&AsciiTile, With<PlayerMarker>>,
+    saver: TileSaver,
+    projectiles: Query<(), With<Projectile>>,
+    mut add: EventWriter<AsciiAddEvent>,
+    mut events: EventWriter<ChunkEvent>,
+) {
+    let authoritative = *mode != NetMode::Client;
+    let centres = players.iter().map(|tile| chunk_of(tile.pos).truncate()).collect::<Vec<_>>();
+    let distance = |chunk: IVec3| centres.iter()
+        .map(|c| (chunk.truncate() - *c).abs().max_element())
+        .min()
+        .unwrap_or(i32::MAX);
+
+    let stale = grid.chunks()
+        .map(|(pos, _)| pos)
+        .filter(|pos| distance(*pos) > UNLOAD_RADIUS)
+        .collect::<Vec<_>>();
+    for chunk_pos in stale {
+        let Some(chunk) = grid.remove_chunk(chunk_pos) else {
+            continue;
+        };
+        let mut tiles = Vec::new();
+        if authoritative {
+            for entity in grid.entities_in(chunk_pos) {
+                if !projectiles.contains(entity) {
+                    let Some(saved) = saver.save(entity) else {
+                        continue;
+                    };
+                    if saved.player {
+                        continue;
+                    }
+                    tiles.push(saved);
+                }
+                commands.entity(entity).despawn_recursive();
+            }
+        }
+        store.chunks.insert(chunk_pos, StoredChunk {
+            terrain: chunk.modified.then_some(chunk),
+            tiles
+        });
+        events.send(ChunkEvent::Unloaded(chunk_pos));
+    }
+
+    let layers = grid.depth() as i32 / CHUNK_SIZE.z;
+    let mut wanted = centres.iter()
+        .flat_map(|c| (-LOAD_RADIUS..=LOAD_RADIUS).flat_map(move |y| (-LOAD_RADIUS..=LOAD_RADIUS).map(move |x| *c + IVec2::new(x, y))))
+        .flat_map(|column| (0..layers).rev().map(move |z| column.extend(z)))
+        .filter(|pos| !grid.is_loaded(*pos))
+        .collect::<Vec<_>>();
+    // Top down, the surface is what players see first.
+    wanted.sort_by_key(|pos| (distance(*pos), -pos.z, pos.x, pos.y));
+    wanted.dedup();
+    for chunk_pos in wanted.into_iter().take(LOADS_PER_FRAME) {
+        match store.chunks.remove(&chunk_pos) {
+            Some(stored) => {
+                let chunk = stored.terrain.unwrap_or_else(|| generate_chunk(&gen, grid.depth(), chunk_pos));
+                grid.insert_chunk(chunk_pos, chunk);
+                for saved in stored.tiles {
+                    spawn_saved_tile(&mut commands, saved, &mut add);
+                }
+            }
+            None => {
+                grid.insert_chunk(chunk_pos, generate_chunk(&gen, grid.depth(), chunk_pos));
+                if authoritative {
+                    let mut rng = population_rng(&gen, chunk_pos);
+                    populate(&mut commands, &grid, &gen, chunk_pos, &mut rng, &mut add);
+                    scatter_items(&mut commands, &grid, &gen, chunk_pos, &mut rng, &mut add);
+                }
+            }
+        }
+        events.send(ChunkEvent::Loaded(chunk_pos));
+    }
+}
+
+pub struct ChunkPlugin;
+impl Plugin for ChunkPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<ChunkStore>()
+            .add_event::<ChunkEvent>()
+            .add_systems(Update, stream_chunks.in_set(ChunkSet).before(AsciiWorldSet));
+    }
+}
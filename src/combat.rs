@@ -0,0 +1,305 @@
+use bevy::prelude::*;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use crate::ascii_world::{Appearance, AsciiAddEvent, AsciiMoveEvent, AsciiRemoveEvent, AsciiTile, Grid};
+use crate::fov::Viewshed;
+use crate::item::{drop_everything, spawn_item, Inventory, Item, ItemKind};
+use crate::living_entity::{Faction, Health};
+use crate::player::SpawnPoint;
+use crate::rng::GameRng;
+use crate::turn::{Tick, TickSet};
+use crate::world_map::{spawn_point, WorldGen};
+
+/// Cells a projectile crosses per tick.
+const PROJECTILE_SPEED: usize = 4;
+const PROJECTILE_APPEARANCE: Appearance = Appearance {
+    glyph: '*',
+    fg: Color::WHITE,
+    bg: Color::NONE,
+    priority: 60
+};
+
+/// Damage dealt by bumping into something, hitting with a chance of `accuracy`.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Attack {
+    pub damage: i32,
+    pub accuracy: f32
+}
+
+/// Fires projectiles at targets up to `range` cells away.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RangedAttack {
+    pub damage: i32,
+    pub range: u32
+}
+
+/// Sent by whoever bumps into `target` with an `Attack`.
+#[derive(Event, Debug, Clone, Copy)]
+pub struct AttackEvent {
+    pub attacker: Entity,
+    pub target: Entity
+}
+
+/// Sent by whoever fires at `target` with a `RangedAttack`.
+#[derive(Event, Debug, Clone, Copy)]
+pub struct FireEvent {
+    pub shooter: Entity,
+    pub target: IVec3
+}
+
+/// What came of an attack or a projectile.
+#[derive(Event, Debug, Clone, Copy)]
+pub enum CombatEvent {
+    Hit { attacker: Entity, target: Entity, damage: i32 },
+    Missed { attacker: Entity, target: Entity },
+    Died { entity: Entity, killer: Entity, pos: IVec3 },
+}
+
+/// A shot in flight, following `path` a few cells per tick until it hits something.
+#[derive(Component)]
+pub struct Projectile {
+    pub shooter: Entity,
+    pub damage: i32,
+    path: Vec<IVec3>,
+    next: usize
+}
+
+/// The cells on the straight line from `from` towards `to`, `from` excluded, continued
+/// past `to` up to `length` cells or the edge of the loaded world.
+pub fn line(grid: &Grid, from: IVec3, to: IVec3, length: u32) -> Vec<IVec3> {
+    let d = to - from;
+    let n = d.abs().max_element();
+    if n == 0 {
+        return Vec::new();
+    }
+    let step = d.as_vec3() / n as f32;
+    (1..=length as i32)
+        .map(|i| (from.as_vec3() + step * i as f32).round().as_ivec3())
+        .take_while(|p| grid.contains(*p))
+        .collect()
+}
+
+/// Whether a shot from `from` reaches `to` without running into solid terrain.
+pub fn line_of_fire(grid: &Grid, from: IVec3, to: IVec3) -> bool {
+    let distance = (to - from).abs().max_element() as u32;
+    line(grid, from, to, distance).iter()
+        .take_while(|p| **p != to)
+        .all(|p| grid.terrain(*p).is_some_and(|t| !t.is_solid()))
+}
+
+/// Whether `from` can hit `to` in melee: next to it on the same layer, and when diagonal
+/// not squeezing past a corner that is solid on both sides.
+pub fn in_reach(grid: &Grid, from: IVec3, to: IVec3) -> bool {
+    let offset = to - from;
+    if offset.z != 0 || offset.abs().max_element() != 1 {
+        return false;
+    }
+    let open = |p: IVec3| grid.terrain(p).is_some_and(|t| !t.is_solid());
+    offset.x == 0 || offset.y == 0 || open(from + offset * IVec3::X) || open(from + offset * IVec3::Y)
+}
+
+/// The closest entity `faction` would attack that is seen, in range and in the line of fire.
+pub fn pick_target(
+    grid: &Grid,
+    from: IVec3,
+    faction: Faction,
+    range: u32,
+    viewshed: Option<&Viewshed>,
+    targets: impl Iterator<Item = (IVec3, Faction)>,
+) -> Option<IVec3> {
+    targets
+        .filter(|(pos, other)| {
+            faction.attacks(*other)
+                && *pos != from
+                && viewshed.map_or(true, |v| v.can_see(*pos))
+                && line_of_fire(grid, from, *pos)
+        })
+        .map(|(pos, _)| (pos, (pos - from).abs().max_element() as u32))
+        .filter(|(_, d)| *d <= range)
+        .min_by_key(|(_, d)| *d)
+        .map(|(pos, _)| pos)
+}
+
+fn hurt(
+    attacker: Entity,
+    target: Entity,
+    damage: i32,
+    health: &mut Health,
+    grid: &Grid,
+    events: &mut EventWriter<CombatEvent>,
+) {
+    if health.current <= 0 {
+        return;
+    }
+    health.current -= damage;
+    events.send(CombatEvent::Hit { attacker, target, damage });
+    if health.current <= 0 {
+        if let Some(pos) = grid.position_of(target) {
+            events.send(CombatEvent::Died { entity: target, killer: attacker, pos });
+        }
+    }
+}
+
+fn resolve_attacks(
+    grid: Res<Grid>,
+    mut attacks: EventReader<AttackEvent>,
+    attackers: Query<&Attack>,
+    mut healths: Query<&mut Health>,
+    mut events: EventWriter<CombatEvent>,
+    mut rng: ResMut<GameRng>,
+) {
+    for ev in attacks.read() {
+        let Ok(attack) = attackers.get(ev.attacker) else {
+            continue;
+        };
+        let Ok(mut health) = healths.get_mut(ev.target) else {
+            continue;
+        };
+        if rng.gen_bool(attack.accuracy.clamp(0., 1.) as f64) {
+            hurt(ev.attacker, ev.target, attack.damage, &mut health, &grid, &mut events);
+        } else {
+            events.send(CombatEvent::Missed { attacker: ev.attacker, target: ev.target });
+        }
+    }
+}
+
+fn launch_projectiles(
+    mut commands: Commands,
+    grid: Res<Grid>,
+    mut fire: EventReader<FireEvent>,
+    shooters: Query<&RangedAttack>,
+    mut add: EventWriter<AsciiAddEvent>,
+) {
+    for ev in fire.read() {
+        let Ok(ranged) = shooters.get(ev.shooter) else {
+            continue;
+        };
+        let Some(pos) = grid.position_of(ev.shooter) else {
+            continue;
+        };
+        let path = line(&grid, pos, ev.target, ranged.range);
+        if path.is_empty() {
+            continue;
+        }
+        let entity = commands.spawn((
+            AsciiTile {pos},
+            PROJECTILE_APPEARANCE,
+            Projectile {
+                shooter: ev.shooter,
+                damage: ranged.damage,
+                path,
+                next: 0
+            },
+        )).id();
+        add.send(AsciiAddEvent {
+            entity,
+            pos
+        });
+    }
+}
+
+/// Moves projectiles cell by cell, so nothing in their way is skipped over.
+pub(crate) fn move_projectiles(
+    mut commands: Commands,
+    mut grid: ResMut<Grid>,
+    mut projectiles: Query<(Entity, &mut AsciiTile, &mut Projectile)>,
+    mut healths: Query<&mut Health>,
+    mut moved: EventWriter<AsciiMoveEvent>,
+    mut removed: EventWriter<AsciiRemoveEvent>,
+    mut events: EventWriter<CombatEvent>,
+) {
+    for (entity, mut tile, mut projectile) in projectiles.iter_mut() {
+        let mut stopped = false;
+        for _ in 0..PROJECTILE_SPEED {
+            let Some(next) = projectile.path.get(projectile.next).copied() else {
+                stopped = true;
+                break;
+            };
+            if grid.terrain(next).map_or(true, |t| t.is_solid()) {
+                stopped = true;
+                break;
+            }
+            let hit = grid.occupants(next).iter()
+                .find(|e| **e != projectile.shooter && healths.contains(**e))
+                .copied();
+            if let Some(target) = hit {
+                let mut health = healths.get_mut(target).unwrap();
+                hurt(projectile.shooter, target, projectile.damage, &mut health, &grid, &mut events);
+                stopped = true;
+                break;
+            }
+            moved.send(AsciiMoveEvent {
+                entity,
+                old_pos: tile.pos,
+                new_pos: next
+            });
+            tile.pos = next;
+            grid.insert(entity, next);
+            projectile.next += 1;
+        }
+        if stopped {
+            grid.remove(entity);
+            removed.send(AsciiRemoveEvent::new(entity, tile.pos));
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
+
+/// Removes the dead, leaving their belongings and a corpse behind. Players get back up
+/// at their `SpawnPoint` instead.
+pub(crate) fn handle_deaths(
+    mut commands: Commands,
+    mut grid: ResMut<Grid>,
+    gen: Res<WorldGen>,
+    mut events: EventReader<CombatEvent>,
+    mut dead: Query<(&mut Health, &mut AsciiTile, Option<&mut Inventory>, Option<&SpawnPoint>)>,
+    mut add: EventWriter<AsciiAddEvent>,
+    mut moved: EventWriter<AsciiMoveEvent>,
+    mut removed: EventWriter<AsciiRemoveEvent>,
+) {
+    for ev in events.read() {
+        let CombatEvent::Died { entity, pos, .. } = *ev else {
+            continue;
+        };
+        let Ok((mut health, mut tile, inventory, spawn)) = dead.get_mut(entity) else {
+            continue;
+        };
+        if let Some(mut inventory) = inventory {
+            drop_everything(&mut commands, &mut inventory, pos, &mut add);
+        }
+        if let Some(spawn) = spawn {
+            let spawn = spawn_point(&grid, &gen, spawn.0.x, spawn.0.y);
+            health.current = health.max;
+            tile.pos = spawn;
+            // Right away, turn based worlds can run many more ticks before the move event is read.
+            grid.insert(entity, spawn);
+            moved.send(AsciiMoveEvent {
+                entity,
+                old_pos: pos,
+                new_pos: spawn
+            });
+            info!("player {:?} died and respawned", entity);
+            continue;
+        }
+        spawn_item(&mut commands, Item::new(ItemKind::Corpse, 1), pos, &mut add);
+        grid.remove(entity);
+        removed.send(AsciiRemoveEvent::new(entity, pos));
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+pub struct CombatPlugin;
+impl Plugin for CombatPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_event::<AttackEvent>()
+            .add_event::<FireEvent>()
+            .add_event::<CombatEvent>()
+            .add_systems(Tick, (
+                resolve_attacks,
+                launch_projectiles,
+                move_projectiles,
+                handle_deaths
+            ).chain().in_set(TickSet::Resolve));
+    }
+}
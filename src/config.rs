@@ -0,0 +1,125 @@
+use std::fs;
+use bevy::prelude::*;
+use bevy::window::{PresentMode, PrimaryWindow, WindowMode};
+use serde::{Deserialize, Serialize};
+
+pub const CONFIG_PATH: &str = "config.ron";
+pub const RESOLUTIONS: [[u32; 2]; 5] = [[1280, 720], [1600, 900], [1920, 1080], [2560, 1440], [3840, 2160]];
+
+#[derive(Resource, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub resolution: [u32; 2],
+    pub fullscreen: bool,
+    pub vsync: bool,
+    /// Screen pixels per atlas pixel of a glyph.
+    pub glyph_scale: f32,
+    /// Cells per second the player moves while a movement key is held.
+    pub movement_speed: f32,
+    /// Atlas image under `assets`, applied to maps built after the change.
+    pub atlas: String,
+    /// Keep the view layer on the player's z.
+    pub follow_player: bool,
+}
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            resolution: [1920, 1080],
+            fullscreen: false,
+            vsync: true,
+            glyph_scale: 1.,
+            movement_speed: 20.,
+            atlas: String::from("atlas.png"),
+            follow_player: true,
+        }
+    }
+}
+impl Config {
+    pub fn window_mode(&self) -> WindowMode {
+        if self.fullscreen { WindowMode::BorderlessFullscreen } else { WindowMode::Windowed }
+    }
+    pub fn present_mode(&self) -> PresentMode {
+        if self.vsync { PresentMode::AutoVsync } else { PresentMode::AutoNoVsync }
+    }
+}
+
+/// Reads the config file, the defaults when there is none. A malformed one is an error,
+/// it is read before logging is set up so the caller reports it.
+pub fn load_config() -> Result<Config, ron::error::SpannedError> {
+    match fs::read_to_string(CONFIG_PATH) {
+        Ok(text) => ron::from_str(&text),
+        Err(_) => Ok(Config::default()),
+    }
+}
+pub fn save_config(config: &Config) {
+    let text = match ron::ser::to_string_pretty(config, ron::ser::PrettyConfig::default()) {
+        Ok(text) => text,
+        Err(e) => {
+            error!("failed to encode config: {}", e);
+            return;
+        }
+    };
+    if let Err(e) = fs::write(CONFIG_PATH, text) {
+        error!("failed to write {}: {}", CONFIG_PATH, e);
+    }
+}
+
+/// Atlas images available in the assets directory.
+pub fn list_atlases() -> Vec<String> {
+    let Ok(dir) = fs::read_dir("assets") else {
+        return Vec::new();
+    };
+    let mut atlases = dir
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .filter(|name| name.ends_with(".png"))
+        .collect::<Vec<_>>();
+    atlases.sort();
+    atlases
+}
+
+fn apply_window(
+    config: Res<Config>,
+    mut window: Query<&mut Window, With<PrimaryWindow>>,
+) {
+    if let Ok(mut window) = window.get_single_mut() {
+        let [width, height] = config.resolution;
+        window.resolution.set(width as f32, height as f32);
+        window.mode = config.window_mode();
+        window.present_mode = config.present_mode();
+    }
+}
+
+fn apply_glyph_scale(
+    config: Res<Config>,
+    mut camera: Query<&mut OrthographicProjection>,
+) {
+    for mut projection in camera.iter_mut() {
+        projection.scale = 1. / config.glyph_scale.max(0.1);
+    }
+}
+
+fn write_config(config: Res<Config>) {
+    if !config.is_added() {
+        save_config(&config);
+    }
+}
+
+pub struct ConfigPlugin;
+impl Plugin for ConfigPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.world.contains_resource::<Config>() {
+            let config = load_config().unwrap_or_else(|e| {
+                error!("ignoring malformed {}: {}", CONFIG_PATH, e);
+                Config::default()
+            });
+            app.insert_resource(config);
+        }
+        app
+            .add_systems(Update, (
+                apply_window,
+                apply_glyph_scale,
+                write_config
+            ).run_if(resource_changed::<Config>));
+    }
+}
@@ -5,8 +5,8 @@ use bevy::diagnostic::*;
 use bevy::ecs::system::lifetimeless::SQuery;
 use bevy::ecs::system::SystemParam;
 use iyes_perf_ui::utils::next_sort_key;
-use crate::ascii_render::ViewLayer;
-use crate::ascii_world::AsciiTile;
+use crate::ascii_world::{AsciiTile, ViewLayer};
+use crate::input::{Action, ActionState};
 use crate::player::{PlayerMarker, PlayerPlugin};
 
 #[derive(States, Debug, Clone, PartialEq, Eq, Hash, Default)]
@@ -24,7 +24,7 @@ struct DisableDebugEvent;
 pub struct PerfUiEntryPlayerPosition {
     pub label: String,
     pub separator: &'static str,
-    pub position: Option<UVec3>,
+    pub position: Option<IVec3>,
     pub width: u8,
     pub sort_key: i32,
 }
@@ -41,7 +41,7 @@ impl Default for PerfUiEntryPlayerPosition {
 }
 impl PerfUiEntry for PerfUiEntryPlayerPosition {
     type SystemParam = (SQuery<&'static AsciiTile, With<PlayerMarker>>);
-    type Value = UVec3;
+    type Value = IVec3;
     fn label(&self) -> &str {
         if self.label.is_empty() {
             "Player Position"
@@ -72,7 +72,7 @@ impl PerfUiEntry for PerfUiEntryPlayerPosition {
 #[derive(Component, Debug, Clone)]
 pub struct PerfUiEntryViewLayer {
     pub label: String,
-    pub position: Option<UVec3>,
+    pub position: Option<IVec3>,
     pub width: u8,
     pub sort_key: i32,
 }
@@ -118,13 +118,13 @@ impl PerfUiEntry for PerfUiEntryViewLayer {
 }
 
 fn keyboard_input(
-    keyboard_input: Res<ButtonInput<KeyCode>>,
+    actions: Res<ActionState>,
     state: Res<State<DebugState>>,
     mut next_state: ResMut<NextState<DebugState>>,
     mut enable_debug: EventWriter<EnableDebugEvent>,
     mut disable_debug: EventWriter<DisableDebugEvent>,
 ) {
-    if keyboard_input.just_pressed(KeyCode::F3) {
+    if actions.just_pressed(Action::ToggleDebug) {
         match state.get() {
             DebugState::Enabled => {
                 disable_debug.send(DisableDebugEvent);
@@ -0,0 +1,269 @@
+use bevy::prelude::*;
+use bevy::utils::HashSet;
+use crate::ascii_world::{AsciiTile, AsciiWorldSet, Grid};
+use crate::player::PlayerMarker;
+use crate::turn::{Tick, TickSet};
+
+pub const PLAYER_SIGHT: u32 = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CellVisibility {
+    Seen,
+    Remembered,
+    Unknown,
+}
+
+/// The cells an entity can currently see from its `AsciiTile`, kept up to date by `update_viewsheds`.
+#[derive(Component)]
+pub struct Viewshed {
+    pub radius: u32,
+    pub visible: HashSet<IVec3>,
+    origin: Option<IVec3>,
+    revision: u64,
+}
+impl Viewshed {
+    pub fn new(radius: u32) -> Self {
+        Self {
+            radius,
+            visible: HashSet::new(),
+            origin: None,
+            revision: 0
+        }
+    }
+    pub fn can_see(&self, pos: IVec3) -> bool {
+        self.visible.contains(&pos)
+    }
+}
+
+/// Every cell a viewer has ever seen.
+#[derive(Component, Default)]
+pub struct Memory {
+    pub cells: HashSet<IVec3>,
+}
+impl Memory {
+    pub fn visibility(&self, viewshed: &Viewshed, pos: IVec3) -> CellVisibility {
+        if viewshed.can_see(pos) {
+            CellVisibility::Seen
+        } else if self.cells.contains(&pos) {
+            CellVisibility::Remembered
+        } else {
+            CellVisibility::Unknown
+        }
+    }
+}
+
+/// Slopes are kept as fractions so the symmetry checks are exact.
+#[derive(Clone, Copy)]
+struct Slope {
+    num: i32,
+    den: i32,
+}
+impl Slope {
+    fn new(num: i32, den: i32) -> Self {
+        Self { num, den }
+    }
+    /// `depth * slope` rounded to the nearest integer, ties rounding up.
+    fn round_up(&self, depth: i32) -> i32 {
+        (2 * depth * self.num + self.den).div_euclid(2 * self.den)
+    }
+    /// `depth * slope` rounded to the nearest integer, ties rounding down.
+    fn round_down(&self, depth: i32) -> i32 {
+        -(self.den - 2 * depth * self.num).div_euclid(2 * self.den)
+    }
+}
+
+struct Row {
+    depth: i32,
+    start: Slope,
+    end: Slope,
+}
+impl Row {
+    fn next(&self) -> Row {
+        Row {
+            depth: self.depth + 1,
+            start: self.start,
+            end: self.end,
+        }
+    }
+    fn is_symmetric(&self, col: i32) -> bool {
+        col * self.start.den >= self.depth * self.start.num && col * self.end.den <= self.depth * self.end.num
+    }
+}
+
+/// Symmetric shadowcasting over one layer: if `a` sees `b` then `b` sees `a`.
+fn shadowcast(origin: IVec2, radius: i32, opaque: &dyn Fn(IVec2) -> bool, reveal: &mut dyn FnMut(IVec2)) {
+    reveal(origin);
+    let quadrants: [fn(IVec2, i32, i32) -> IVec2; 4] = [
+        |o, depth, col| IVec2::new(o.x + col, o.y - depth),
+        |o, depth, col| IVec2::new(o.x + col, o.y + depth),
+        |o, depth, col| IVec2::new(o.x + depth, o.y + col),
+        |o, depth, col| IVec2::new(o.x - depth, o.y + col),
+    ];
+    for transform in quadrants {
+        let mut rows = vec![Row { depth: 1, start: Slope::new(-1, 1), end: Slope::new(1, 1) }];
+        while let Some(mut row) = rows.pop() {
+            if row.depth > radius {
+                continue;
+            }
+            let mut prev_opaque: Option<bool> = None;
+            for col in row.start.round_up(row.depth)..=row.end.round_down(row.depth) {
+                let cell = transform(origin, row.depth, col);
+                let is_opaque = opaque(cell);
+                let in_range = col * col + row.depth * row.depth <= radius * radius + radius;
+                if in_range && (is_opaque || row.is_symmetric(col)) {
+                    reveal(cell);
+                }
+                if prev_opaque == Some(true) && !is_opaque {
+                    row.start = Slope::new(2 * col - 1, 2 * row.depth);
+                }
+                if prev_opaque == Some(false) && is_opaque {
+                    let mut next = row.next();
+                    next.end = Slope::new(2 * col - 1, 2 * row.depth);
+                    rows.push(next);
+                }
+                prev_opaque = Some(is_opaque);
+            }
+            if prev_opaque == Some(false) {
+                rows.push(row.next());
+            }
+        }
+    }
+}
+
+/// The cells visible from `origin`: shadowcast across its layer, then down through
+/// open cells onto whatever lies below them.
+pub fn compute_fov(grid: &Grid, origin: IVec3, radius: u32) -> HashSet<IVec3> {
+    let mut visible = HashSet::new();
+    let z = origin.z;
+    let opaque = |p: IVec2| grid.terrain(p.extend(z)).map_or(true, |t| t.is_solid());
+    let mut reveal = |p: IVec2| {
+        if grid.contains(p.extend(z)) {
+            visible.insert(p.extend(z));
+        }
+    };
+    shadowcast(origin.truncate(), radius as i32, &opaque, &mut reveal);
+    let layer = visible.iter().copied().collect::<Vec<_>>();
+    for pos in layer {
+        let mut below = pos;
+        while below.z > 0 && grid.terrain(below).is_some_and(|t| !t.is_solid()) {
+            below -= IVec3::Z;
+            visible.insert(below);
+        }
+    }
+    visible
+}
+
+/// Gives every player a viewshed and memory, wherever they were spawned. Loaded players
+/// bring their memory along.
+fn add_player_viewsheds(
+    mut commands: Commands,
+    players: Query<(Entity, Has<Memory>), (Added<PlayerMarker>, Without<Viewshed>)>,
+) {
+    for (entity, remembers) in players.iter() {
+        commands.entity(entity).insert(Viewshed::new(PLAYER_SIGHT));
+        if !remembers {
+            commands.entity(entity).insert(Memory::default());
+        }
+    }
+}
+
+fn update_viewsheds(
+    grid: Res<Grid>,
+    mut viewers: Query<(&AsciiTile, &mut Viewshed)>,
+) {
+    for (tile, mut viewshed) in viewers.iter_mut() {
+        // `compute_fov` looks across the layer and down from it.
+        let reach = IVec2::splat(viewshed.radius as i32);
+        let revision = grid.revision_in((tile.pos.truncate() - reach).extend(0), (tile.pos.truncate() + reach).extend(tile.pos.z));
+        if viewshed.origin == Some(tile.pos) && viewshed.revision == revision {
+            continue;
+        }
+        viewshed.visible = compute_fov(&grid, tile.pos, viewshed.radius);
+        viewshed.origin = Some(tile.pos);
+        viewshed.revision = revision;
+    }
+}
+
+fn remember(
+    mut viewers: Query<(&Viewshed, &mut Memory), Changed<Viewshed>>,
+) {
+    for (viewshed, mut memory) in viewers.iter_mut() {
+        memory.cells.extend(viewshed.visible.iter().copied());
+    }
+}
+
+/// Runs after movement so views match this frame's positions.
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FovSet;
+
+pub struct FovPlugin;
+impl Plugin for FovPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_systems(Update, (
+                add_player_viewsheds,
+                update_viewsheds,
+                remember
+            ).chain().in_set(FovSet).after(AsciiWorldSet))
+            // Creatures look around again between turns taken within one frame.
+            .add_systems(Tick, update_viewsheds.after(TickSet::Players).before(TickSet::Npcs));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::utils::HashMap;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use super::*;
+    use crate::ascii_world::Terrain;
+    use crate::chunk::Chunk;
+
+    const SIZE: i32 = 24;
+
+    /// A layer of floor with scattered pillars.
+    fn pillars(seed: u64) -> Grid {
+        let mut grid = Grid::new(1);
+        grid.insert_chunk(IVec3::ZERO, Chunk::default());
+        let mut rng = StdRng::seed_from_u64(seed);
+        for y in 0..SIZE {
+            for x in 0..SIZE {
+                if rng.gen_bool(0.2) {
+                    grid.set_terrain(IVec3::new(x, y, 0), Terrain::Stone);
+                }
+            }
+        }
+        grid
+    }
+
+    #[test]
+    fn open_cells_see_each_other_symmetrically() {
+        for seed in 0..4 {
+            let grid = pillars(seed);
+            let open = (0..SIZE).flat_map(|y| (0..SIZE).map(move |x| IVec3::new(x, y, 0)))
+                .filter(|p| grid.terrain(*p).is_some_and(|t| !t.is_solid()))
+                .collect::<Vec<_>>();
+            let fovs = open.iter().map(|p| (*p, compute_fov(&grid, *p, 8))).collect::<HashMap<_, _>>();
+            for a in &open {
+                assert!(fovs[a].contains(a));
+                for b in &open {
+                    assert_eq!(fovs[a].contains(b), fovs[b].contains(a), "seed {} from {} to {}", seed, a, b);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn walls_are_seen_but_hide_what_is_behind() {
+        let mut grid = Grid::new(1);
+        grid.insert_chunk(IVec3::ZERO, Chunk::default());
+        for y in 0..SIZE {
+            grid.set_terrain(IVec3::new(10, y, 0), Terrain::Stone);
+        }
+        let visible = compute_fov(&grid, IVec3::new(8, 8, 0), 8);
+        assert!(visible.contains(&IVec3::new(10, 8, 0)));
+        assert!(!visible.contains(&IVec3::new(11, 8, 0)));
+        assert!(visible.contains(&IVec3::new(8, 15, 0)));
+        assert!(!visible.contains(&IVec3::new(8, 17, 0)));
+    }
+}
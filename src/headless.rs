@@ -0,0 +1,77 @@
+use std::path::PathBuf;
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use rand::Rng;
+use crate::ascii_world::WorldSettings;
+use crate::rng::GameRng;
+use crate::save::{LoadEvent, SaveEvent, SaveSlot};
+use crate::turn::{TickCount, TimeMode};
+use crate::world_map::{seed_from_str, NewWorldEvent};
+
+/// What a headless run starts from and how long it runs, there is no menu to pick either.
+#[derive(Resource, Default)]
+pub struct HeadlessSettings {
+    /// Save file to load, a new world is created when there is none.
+    pub load: Option<PathBuf>,
+    pub name: Option<String>,
+    /// Seed text for a new world, see `seed_from_str`.
+    pub seed: Option<String>,
+    /// Ticks to run before saving and exiting, forever if unset. Turn based worlds
+    /// only tick when their players act, so they need connected players to get there.
+    pub ticks: Option<u64>,
+}
+
+fn start(
+    settings: Res<HeadlessSettings>,
+    world: Res<WorldSettings>,
+    slot: Res<SaveSlot>,
+    mut rng: ResMut<GameRng>,
+    mut load: EventWriter<LoadEvent>,
+    mut new_world: EventWriter<NewWorldEvent>,
+) {
+    if let Some(path) = &settings.load {
+        load.send(LoadEvent(path.clone()));
+        return;
+    }
+    let name = settings.name.clone().unwrap_or_else(|| slot.0.clone());
+    let seed = settings.seed.as_deref().map(seed_from_str).unwrap_or_else(|| rng.gen());
+    info!("creating world {} with seed {}", name, seed);
+    new_world.send(NewWorldEvent {
+        name,
+        seed,
+        depth: world.depth,
+        time_mode: TimeMode::RealTime
+    });
+}
+
+/// Saves once the tick limit is reached and exits on the frame after, when the save is written.
+fn finish(
+    settings: Res<HeadlessSettings>,
+    count: Res<TickCount>,
+    mut saved: Local<bool>,
+    mut save: EventWriter<SaveEvent>,
+    mut exit: EventWriter<AppExit>,
+) {
+    let Some(ticks) = settings.ticks else {
+        return;
+    };
+    if *saved {
+        info!("ran {} ticks", count.0);
+        exit.send(AppExit);
+    } else if count.0 >= ticks {
+        save.send(SaveEvent);
+        *saved = true;
+    }
+}
+
+/// Stands in for the main menu when nothing is drawn: starts a world and, given a tick
+/// limit, saves it and exits once the limit is reached.
+pub struct HeadlessPlugin;
+impl Plugin for HeadlessPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<HeadlessSettings>()
+            .add_systems(Startup, start)
+            .add_systems(PreUpdate, finish);
+    }
+}
@@ -0,0 +1,379 @@
+use std::fmt;
+use std::fs;
+use bevy::input::InputSystem;
+use bevy::prelude::*;
+use bevy::reflect::{DynamicEnum, DynamicVariant, Enum};
+use bevy::utils::{HashMap, HashSet};
+use serde::{Deserialize, Serialize};
+use crate::MainState;
+
+const BINDINGS_PATH: &str = "bindings.ron";
+const AXIS_THRESHOLD: f32 = 0.5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Action {
+    MoveNorth,
+    MoveSouth,
+    MoveWest,
+    MoveEast,
+    MoveUp,
+    MoveDown,
+    MenuNext,
+    MenuPrevious,
+    MenuUp,
+    MenuDown,
+    MenuConfirm,
+    MenuBack,
+    TextDelete,
+    CameraModifier,
+    CameraDrag,
+    ToggleDebug,
+    PickUp,
+    Inventory,
+    Fire,
+    Dig,
+    Build,
+    CycleStructure,
+    MessageHistory,
+    QuickSave,
+}
+
+/// Binding tables are looked up per context, `Global` is active in addition to the current one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum InputContext {
+    Global,
+    Menu,
+    Text,
+    Game,
+}
+
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ActiveContext(pub InputContext);
+impl FromWorld for ActiveContext {
+    fn from_world(_world: &mut World) -> Self {
+        Self(InputContext::Menu)
+    }
+}
+
+/// A physical input, written to the bindings file as e.g. `"Key:KeyW"`, `"Mouse:Left"`,
+/// `"Pad:South"` or `"Axis:LeftStickY+"`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Binding {
+    Key(KeyCode),
+    Mouse(MouseButton),
+    Pad(GamepadButtonType),
+    Axis(GamepadAxisType, bool),
+}
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Binding::Key(key) => write!(f, "Key:{}", key.variant_name()),
+            Binding::Mouse(button) => write!(f, "Mouse:{}", button.variant_name()),
+            Binding::Pad(button) => write!(f, "Pad:{}", button.variant_name()),
+            Binding::Axis(axis, positive) => write!(f, "Axis:{}{}", axis.variant_name(), if *positive { '+' } else { '-' }),
+        }
+    }
+}
+impl From<Binding> for String {
+    fn from(binding: Binding) -> Self {
+        binding.to_string()
+    }
+}
+impl TryFrom<String> for Binding {
+    type Error = String;
+    fn try_from(text: String) -> Result<Self, Self::Error> {
+        fn unit<T: FromReflect>(name: &str) -> Option<T> {
+            T::from_reflect(&DynamicEnum::new(name, DynamicVariant::Unit))
+        }
+        let (device, name) = text.split_once(':').ok_or_else(|| format!("missing device in binding {}", text))?;
+        let binding = match device {
+            "Key" => unit(name).map(Binding::Key),
+            "Mouse" => unit(name).map(Binding::Mouse),
+            "Pad" => unit(name).map(Binding::Pad),
+            "Axis" => {
+                let positive = name.ends_with('+');
+                name.strip_suffix(['+', '-']).and_then(unit).map(|axis| Binding::Axis(axis, positive))
+            }
+            _ => None,
+        };
+        binding.ok_or_else(|| format!("unknown binding {}", text))
+    }
+}
+
+#[derive(Resource, Debug, Clone, Serialize, Deserialize)]
+pub struct InputMap {
+    pub contexts: HashMap<InputContext, HashMap<Action, Vec<Binding>>>,
+}
+impl Default for InputMap {
+    fn default() -> Self {
+        use Action::*;
+        use Binding::*;
+        let table = |entries: Vec<(Action, Vec<Binding>)>| entries.into_iter().collect::<HashMap<_, _>>();
+        let mut contexts = HashMap::new();
+        contexts.insert(InputContext::Global, table(vec![
+            (ToggleDebug, vec![Key(KeyCode::F3)]),
+        ]));
+        contexts.insert(InputContext::Menu, table(vec![
+            (MenuNext, vec![Key(KeyCode::Tab), Key(KeyCode::KeyD), Key(KeyCode::ArrowRight), Pad(GamepadButtonType::DPadRight)]),
+            (MenuPrevious, vec![Key(KeyCode::KeyA), Key(KeyCode::ArrowLeft), Pad(GamepadButtonType::DPadLeft)]),
+            (MenuUp, vec![Key(KeyCode::KeyW), Key(KeyCode::ArrowUp), Pad(GamepadButtonType::DPadUp)]),
+            (MenuDown, vec![Key(KeyCode::KeyS), Key(KeyCode::ArrowDown), Pad(GamepadButtonType::DPadDown)]),
+            (MenuConfirm, vec![Key(KeyCode::Enter), Pad(GamepadButtonType::South)]),
+            (MenuBack, vec![Key(KeyCode::Escape), Pad(GamepadButtonType::East)]),
+        ]));
+        contexts.insert(InputContext::Text, table(vec![
+            (MenuUp, vec![Key(KeyCode::ArrowUp), Pad(GamepadButtonType::DPadUp)]),
+            (MenuDown, vec![Key(KeyCode::ArrowDown), Key(KeyCode::Tab), Pad(GamepadButtonType::DPadDown)]),
+            (MenuNext, vec![Key(KeyCode::ArrowRight), Pad(GamepadButtonType::DPadRight)]),
+            (MenuPrevious, vec![Key(KeyCode::ArrowLeft), Pad(GamepadButtonType::DPadLeft)]),
+            (MenuConfirm, vec![Key(KeyCode::Enter), Pad(GamepadButtonType::South)]),
+            (MenuBack, vec![Key(KeyCode::Escape), Pad(GamepadButtonType::East)]),
+            (TextDelete, vec![Key(KeyCode::Backspace)]),
+        ]));
+        contexts.insert(InputContext::Game, table(vec![
+            (MoveNorth, vec![Key(KeyCode::KeyW), Pad(GamepadButtonType::DPadUp), Axis(GamepadAxisType::LeftStickY, true)]),
+            (MoveSouth, vec![Key(KeyCode::KeyS), Pad(GamepadButtonType::DPadDown), Axis(GamepadAxisType::LeftStickY, false)]),
+            (MoveWest, vec![Key(KeyCode::KeyA), Pad(GamepadButtonType::DPadLeft), Axis(GamepadAxisType::LeftStickX, false)]),
+            (MoveEast, vec![Key(KeyCode::KeyD), Pad(GamepadButtonType::DPadRight), Axis(GamepadAxisType::LeftStickX, true)]),
+            (MoveUp, vec![Key(KeyCode::Comma), Pad(GamepadButtonType::LeftTrigger)]),
+            (MoveDown, vec![Key(KeyCode::Period), Pad(GamepadButtonType::RightTrigger)]),
+            (CameraModifier, vec![Key(KeyCode::ControlLeft)]),
+            (CameraDrag, vec![Mouse(MouseButton::Left), Mouse(MouseButton::Right)]),
+            (QuickSave, vec![Key(KeyCode::F5)]),
+            (PickUp, vec![Key(KeyCode::KeyG), Pad(GamepadButtonType::West)]),
+            (Inventory, vec![Key(KeyCode::KeyI), Pad(GamepadButtonType::North)]),
+            (Fire, vec![Key(KeyCode::KeyF), Pad(GamepadButtonType::RightTrigger2)]),
+            (Dig, vec![Key(KeyCode::KeyX), Pad(GamepadButtonType::LeftTrigger2)]),
+            (Build, vec![Key(KeyCode::KeyB), Pad(GamepadButtonType::RightThumb)]),
+            (CycleStructure, vec![Key(KeyCode::KeyV), Pad(GamepadButtonType::Select)]),
+            (MessageHistory, vec![Key(KeyCode::KeyM)]),
+        ]));
+        Self {
+            contexts
+        }
+    }
+}
+impl InputMap {
+    /// Adds the default bindings of actions the map does not mention, e.g. actions newer
+    /// than the bindings file.
+    pub fn merge_defaults(&mut self) {
+        for (context, table) in InputMap::default().contexts {
+            let current = self.contexts.entry(context).or_default();
+            for (action, bindings) in table {
+                current.entry(action).or_insert(bindings);
+            }
+        }
+    }
+    pub fn bindings(&self, context: InputContext, action: Action) -> &[Binding] {
+        self.contexts.get(&context)
+            .and_then(|table| table.get(&action))
+            .map(|bindings| bindings.as_slice())
+            .unwrap_or(&[])
+    }
+    /// Bindings shared by two different actions that can be active at the same time,
+    /// i.e. within one context or between a context and `Global`.
+    pub fn conflicts(&self) -> Vec<(InputContext, Binding, Action, Action)> {
+        let global = self.contexts.get(&InputContext::Global);
+        let mut conflicts = Vec::new();
+        for (context, table) in self.contexts.iter() {
+            let mut seen: HashMap<Binding, Action> = HashMap::new();
+            let entries = table.iter()
+                .chain(global.filter(|_| *context != InputContext::Global).into_iter().flatten());
+            for (action, bindings) in entries {
+                for binding in bindings {
+                    match seen.get(binding) {
+                        Some(other) if other != action => conflicts.push((*context, *binding, *other, *action)),
+                        _ => {
+                            seen.insert(*binding, *action);
+                        }
+                    }
+                }
+            }
+        }
+        conflicts
+    }
+}
+
+/// Reads the bindings file, writing the defaults out when it does not exist yet.
+pub fn load_input_map() -> InputMap {
+    match fs::read_to_string(BINDINGS_PATH) {
+        Ok(text) => match ron::from_str::<InputMap>(&text) {
+            Ok(mut map) => {
+                map.merge_defaults();
+                map
+            }
+            Err(e) => {
+                error!("ignoring malformed {}: {}", BINDINGS_PATH, e);
+                InputMap::default()
+            }
+        },
+        Err(_) => {
+            let map = InputMap::default();
+            match ron::ser::to_string_pretty(&map, ron::ser::PrettyConfig::default()) {
+                Ok(text) => {
+                    if let Err(e) = fs::write(BINDINGS_PATH, text) {
+                        warn!("failed to write {}: {}", BINDINGS_PATH, e);
+                    }
+                }
+                Err(e) => warn!("failed to encode default bindings: {}", e),
+            }
+            map
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct ActionState {
+    pressed: HashSet<Action>,
+    just_pressed: HashSet<Action>,
+    just_released: HashSet<Action>,
+}
+impl ActionState {
+    pub fn pressed(&self, action: Action) -> bool {
+        self.pressed.contains(&action)
+    }
+    pub fn just_pressed(&self, action: Action) -> bool {
+        self.just_pressed.contains(&action)
+    }
+    pub fn just_released(&self, action: Action) -> bool {
+        self.just_released.contains(&action)
+    }
+}
+
+fn binding_pressed(
+    binding: &Binding,
+    key: &ButtonInput<KeyCode>,
+    mouse: &ButtonInput<MouseButton>,
+    gamepads: &Gamepads,
+    pad_buttons: &ButtonInput<GamepadButton>,
+    pad_axes: &Axis<GamepadAxis>,
+) -> bool {
+    match binding {
+        Binding::Key(k) => key.pressed(*k),
+        Binding::Mouse(button) => mouse.pressed(*button),
+        Binding::Pad(button) => gamepads.iter().any(|pad| pad_buttons.pressed(GamepadButton::new(pad, *button))),
+        Binding::Axis(axis, positive) => gamepads.iter().any(|pad| {
+            let value = pad_axes.get(GamepadAxis::new(pad, *axis)).unwrap_or(0.);
+            if *positive { value > AXIS_THRESHOLD } else { value < -AXIS_THRESHOLD }
+        }),
+    }
+}
+
+/// Updates `ActionState`, systems reading it in `PreUpdate` go after it.
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct InputMapSet;
+
+fn update_action_state(
+    map: Res<InputMap>,
+    context: Res<ActiveContext>,
+    key: Res<ButtonInput<KeyCode>>,
+    mouse: Res<ButtonInput<MouseButton>>,
+    gamepads: Res<Gamepads>,
+    pad_buttons: Res<ButtonInput<GamepadButton>>,
+    pad_axes: Res<Axis<GamepadAxis>>,
+    mut state: ResMut<ActionState>,
+) {
+    let mut pressed = HashSet::new();
+    for context in [InputContext::Global, context.0] {
+        if let Some(table) = map.contexts.get(&context) {
+            for (action, bindings) in table.iter() {
+                if bindings.iter().any(|b| binding_pressed(b, &key, &mouse, &gamepads, &pad_buttons, &pad_axes)) {
+                    pressed.insert(*action);
+                }
+            }
+        }
+    }
+    state.just_pressed = pressed.difference(&state.pressed).copied().collect();
+    state.just_released = state.pressed.difference(&pressed).copied().collect();
+    state.pressed = pressed;
+}
+
+fn report_conflicts(map: Res<InputMap>) {
+    for (context, binding, a, b) in map.conflicts() {
+        warn!("{} is bound to both {:?} and {:?} in {:?}", binding, a, b, context);
+    }
+}
+
+fn set_context<const GAME: bool>(mut context: ResMut<ActiveContext>) {
+    context.0 = if GAME { InputContext::Game } else { InputContext::Menu };
+}
+
+pub struct InputMapPlugin;
+impl Plugin for InputMapPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.world.contains_resource::<InputMap>() {
+            app.insert_resource(load_input_map());
+        }
+        app
+            .init_resource::<ActiveContext>()
+            .init_resource::<ActionState>()
+            .add_systems(Startup, report_conflicts)
+            .add_systems(OnEnter(MainState::MainMenu), set_context::<false>)
+            .add_systems(OnEnter(MainState::InGame), set_context::<true>)
+            .add_systems(PreUpdate, update_action_state.in_set(InputMapSet).after(InputSystem));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bindings_round_trip_through_strings() {
+        let bindings = [
+            (Binding::Key(KeyCode::KeyW), "Key:KeyW"),
+            (Binding::Mouse(MouseButton::Left), "Mouse:Left"),
+            (Binding::Pad(GamepadButtonType::South), "Pad:South"),
+            (Binding::Axis(GamepadAxisType::LeftStickY, true), "Axis:LeftStickY+"),
+            (Binding::Axis(GamepadAxisType::RightStickX, false), "Axis:RightStickX-"),
+        ];
+        for (binding, text) in bindings {
+            assert_eq!(binding.to_string(), text);
+            assert_eq!(Binding::try_from(String::from(text)), Ok(binding));
+        }
+    }
+
+    #[test]
+    fn unknown_bindings_are_rejected() {
+        for text in ["KeyW", "Key:NoSuchKey", "Joystick:South", "Axis:LeftStickY", "Axis:South+", "Pad:KeyW"] {
+            assert!(Binding::try_from(String::from(text)).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn bindings_file_round_trips() {
+        let map = InputMap::default();
+        let text = ron::ser::to_string_pretty(&map, ron::ser::PrettyConfig::default()).unwrap();
+        let read = ron::from_str::<InputMap>(&text).unwrap();
+        assert_eq!(read.contexts, map.contexts);
+    }
+
+    #[test]
+    fn defaults_have_no_conflicts() {
+        assert!(InputMap::default().conflicts().is_empty());
+    }
+
+    #[test]
+    fn conflicts_within_a_context_and_with_global() {
+        let mut map = InputMap::default();
+        map.contexts.get_mut(&InputContext::Game).unwrap().insert(Action::Fire, vec![Binding::Key(KeyCode::KeyW)]);
+        map.contexts.get_mut(&InputContext::Menu).unwrap().insert(Action::MenuBack, vec![Binding::Key(KeyCode::F3)]);
+        let mut conflicts = map.conflicts();
+        conflicts.sort_by_key(|(context, ..)| *context as u8);
+        assert_eq!(conflicts.len(), 2);
+        let (context, binding, a, b) = conflicts[0];
+        assert_eq!((context, binding), (InputContext::Menu, Binding::Key(KeyCode::F3)));
+        assert!([a, b].contains(&Action::MenuBack) && [a, b].contains(&Action::ToggleDebug));
+        let (context, binding, a, b) = conflicts[1];
+        assert_eq!((context, binding), (InputContext::Game, Binding::Key(KeyCode::KeyW)));
+        assert!([a, b].contains(&Action::Fire) && [a, b].contains(&Action::MoveNorth));
+    }
+
+    #[test]
+    fn same_binding_in_separate_contexts_is_fine() {
+        let mut map = InputMap::default();
+        map.contexts.get_mut(&InputContext::Text).unwrap().insert(Action::TextDelete, vec![Binding::Key(KeyCode::KeyD)]);
+        assert!(map.conflicts().is_empty());
+    }
+}
@@ -0,0 +1,133 @@
+use bevy::prelude::*;
+use bevy_fast_tilemap::Map;
+use crate::ascii_render::{AsciiAtlas, UserData};
+use crate::input::{Action, ActionState, ActiveContext, InputContext};
+use crate::item::{Inventory, Item, ItemAction};
+use crate::MainState;
+use crate::player::{PlayerCommand, PlayerMarker};
+use crate::ui::{blank_map, spawn_panel, write_row};
+
+const SCREEN_SIZE: UVec2 = UVec2::new(48, 18);
+const FIRST_ROW: u32 = 3;
+
+#[derive(Component)]
+struct InventoryScreen {
+    selected: usize
+}
+
+fn toggle_inventory_screen(
+    mut commands: Commands,
+    actions: Res<ActionState>,
+    ascii_atlas: Res<AsciiAtlas>,
+    mut materials: ResMut<Assets<Map<UserData>>>,
+    mut context: ResMut<ActiveContext>,
+    screen: Query<Entity, With<InventoryScreen>>,
+    camera: Query<Entity, With<Camera>>,
+) {
+    if let Ok(entity) = screen.get_single() {
+        if actions.just_pressed(Action::MenuBack) {
+            commands.entity(entity).despawn_recursive();
+            context.0 = InputContext::Game;
+        }
+        return;
+    }
+    if !actions.just_pressed(Action::Inventory) {
+        return;
+    }
+    let Ok(camera) = camera.get_single() else {
+        return;
+    };
+    let map = blank_map(&ascii_atlas, SCREEN_SIZE, Color::rgba(0., 0., 0., 0.85));
+    spawn_panel(&mut commands, &mut materials, camera, map, -100., InventoryScreen { selected: 0 });
+    context.0 = InputContext::Menu;
+}
+
+fn inventory_screen_input(
+    actions: Res<ActionState>,
+    mut screen: Query<&mut InventoryScreen>,
+    player: Query<&Inventory, With<PlayerMarker>>,
+    mut command: EventWriter<PlayerCommand>,
+) {
+    let Ok(mut screen) = screen.get_single_mut() else {
+        return;
+    };
+    let len = player.get_single().map(|inventory| inventory.items.len()).unwrap_or(0);
+    if len == 0 {
+        return;
+    }
+    if actions.just_pressed(Action::MenuDown) {
+        screen.selected = (screen.selected + 1) % len;
+    }
+    if actions.just_pressed(Action::MenuUp) {
+        screen.selected = (screen.selected + len - 1) % len;
+    }
+    screen.selected = screen.selected.min(len - 1);
+    if actions.just_pressed(Action::MenuConfirm) {
+        command.send(PlayerCommand {
+            item: Some(ItemAction::Drop(screen.selected)),
+            ..default()
+        });
+    }
+}
+
+fn draw_inventory_screen(
+    mut materials: ResMut<Assets<Map<UserData>>>,
+    screen: Query<(&Handle<Map<UserData>>, &InventoryScreen)>,
+    player: Query<&Inventory, With<PlayerMarker>>,
+    items: Query<&Item>,
+) {
+    let Ok((map_handle, screen)) = screen.get_single() else {
+        return;
+    };
+    let map = materials.get_mut(map_handle).unwrap();
+    let background = Color::rgba(0., 0., 0., 0.85);
+    let inventory = player.get_single().ok();
+    let held = inventory
+        .map(|inventory| inventory.items.iter().filter_map(|e| items.get(*e).ok()).collect::<Vec<_>>())
+        .unwrap_or_default();
+    let weight = held.iter().map(|item| item.weight()).sum::<f32>();
+    let volume = held.iter().map(|item| item.volume()).sum::<f32>();
+    let (max_weight, max_volume) = inventory.map(|i| (i.max_weight, i.max_volume)).unwrap_or_default();
+
+    write_row(map, 0, " Inventory", Color::WHITE, background);
+    write_row(map, 1, &format!(" {:.1}/{:.1} kg   {:.1}/{:.1} l", weight, max_weight, volume, max_volume), Color::GRAY, background);
+    for row in FIRST_ROW..SCREEN_SIZE.y - 1 {
+        let i = (row - FIRST_ROW) as usize;
+        match held.get(i) {
+            Some(item) => {
+                let letter = (b'a' + (i % 26) as u8) as char;
+                let text = format!(" {}) {:<30}{:>6.2} kg", letter, item.describe(), item.weight());
+                if i == screen.selected {
+                    write_row(map, row, &text, Color::BLUE, Color::WHITE);
+                } else {
+                    write_row(map, row, &text, item.kind.stats().appearance.fg, background);
+                }
+            }
+            None if i == 0 => write_row(map, row, " Nothing carried", Color::GRAY, background),
+            None => write_row(map, row, "", Color::WHITE, background),
+        }
+    }
+    write_row(map, SCREEN_SIZE.y - 1, " Enter: drop   Esc: close", Color::GRAY, background);
+}
+
+fn despawn_inventory_screen(
+    mut commands: Commands,
+    screen: Query<Entity, With<InventoryScreen>>,
+) {
+    for entity in screen.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+pub struct InventoryScreenPlugin;
+impl Plugin for InventoryScreenPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_systems(Update, (
+                toggle_inventory_screen,
+                inventory_screen_input,
+                draw_inventory_screen
+            ).chain().run_if(in_state(MainState::InGame)))
+            .add_systems(OnExit(MainState::InGame), despawn_inventory_screen);
+    }
+}
@@ -0,0 +1,369 @@
+use bevy::prelude::*;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use crate::ascii_world::{Appearance, AsciiAddEvent, AsciiRemoveEvent, AsciiTile, Gravity, Grid, Terrain};
+use crate::chunk::{chunk_of, CHUNK_SIZE};
+use crate::player::apply_commands;
+use crate::turn::{Tick, TickSet};
+use crate::world_map::WorldGen;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ItemKind {
+    Rock,
+    Stick,
+    Apple,
+    Coin,
+    Torch,
+    Corpse,
+}
+pub struct ItemStats {
+    pub name: &'static str,
+    pub plural: &'static str,
+    pub appearance: Appearance,
+    /// Kilograms per item.
+    pub weight: f32,
+    /// Litres per item.
+    pub volume: f32,
+    pub max_stack: u32,
+}
+impl ItemKind {
+    pub const ALL: [ItemKind; 6] = [ItemKind::Rock, ItemKind::Stick, ItemKind::Apple, ItemKind::Coin, ItemKind::Torch, ItemKind::Corpse];
+
+    pub fn stats(&self) -> ItemStats {
+        match self {
+            ItemKind::Rock => ItemStats {
+                name: "rock",
+                plural: "rocks",
+                appearance: Appearance { glyph: '*', fg: Color::GRAY, bg: Color::NONE, priority: 10 },
+                weight: 0.5,
+                volume: 0.3,
+                max_stack: 20,
+            },
+            ItemKind::Stick => ItemStats {
+                name: "stick",
+                plural: "sticks",
+                appearance: Appearance { glyph: '/', fg: Color::rgb(0.6, 0.4, 0.2), bg: Color::NONE, priority: 10 },
+                weight: 0.2,
+                volume: 0.5,
+                max_stack: 20,
+            },
+            ItemKind::Apple => ItemStats {
+                name: "apple",
+                plural: "apples",
+                appearance: Appearance { glyph: '%', fg: Color::RED, bg: Color::NONE, priority: 10 },
+                weight: 0.15,
+                volume: 0.2,
+                max_stack: 10,
+            },
+            ItemKind::Coin => ItemStats {
+                name: "coin",
+                plural: "coins",
+                appearance: Appearance { glyph: '$', fg: Color::GOLD, bg: Color::NONE, priority: 10 },
+                weight: 0.01,
+                volume: 0.001,
+                max_stack: 1000,
+            },
+            ItemKind::Torch => ItemStats {
+                name: "torch",
+                plural: "torches",
+                appearance: Appearance { glyph: '!', fg: Color::ORANGE, bg: Color::NONE, priority: 10 },
+                weight: 0.5,
+                volume: 0.6,
+                max_stack: 5,
+            },
+            ItemKind::Corpse => ItemStats {
+                name: "corpse",
+                plural: "corpses",
+                appearance: Appearance { glyph: '%', fg: Color::MAROON, bg: Color::NONE, priority: 5 },
+                weight: 10.,
+                volume: 20.,
+                max_stack: 1,
+            },
+        }
+    }
+}
+
+/// A stack of `count` items of one kind, lying on the grid when it has an `AsciiTile`
+/// and held in an `Inventory` otherwise.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Item {
+    pub kind: ItemKind,
+    pub count: u32
+}
+impl Item {
+    pub fn new(kind: ItemKind, count: u32) -> Self {
+        Self { kind, count }
+    }
+    pub fn weight(&self) -> f32 {
+        self.kind.stats().weight * self.count as f32
+    }
+    pub fn volume(&self) -> f32 {
+        self.kind.stats().volume * self.count as f32
+    }
+    /// "a rock", "3 rocks".
+    pub fn describe(&self) -> String {
+        let stats = self.kind.stats();
+        if self.count == 1 {
+            format!("a {}", stats.name)
+        } else {
+            format!("{} {}", self.count, stats.plural)
+        }
+    }
+}
+
+/// Item stacks carried by an entity, limited by their total weight and volume.
+/// The stacks are children of the holder, so they go wherever it goes, despawning included.
+#[derive(Component, Debug, Clone)]
+pub struct Inventory {
+    pub items: Vec<Entity>,
+    pub max_weight: f32,
+    pub max_volume: f32
+}
+impl Inventory {
+    pub fn new(max_weight: f32, max_volume: f32) -> Self {
+        Self {
+            items: Vec::new(),
+            max_weight,
+            max_volume
+        }
+    }
+    /// Total weight and volume of the carried items.
+    pub fn load(&self, items: &Query<&mut Item>) -> (f32, f32) {
+        self.items.iter()
+            .filter_map(|e| items.get(*e).ok())
+            .fold((0., 0.), |(w, v), item| (w + item.weight(), v + item.volume()))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ItemAction {
+    /// Picks up everything lying in the actor's cell that fits.
+    PickUp,
+    /// Drops the stack at this index of the actor's inventory.
+    Drop(usize),
+}
+
+#[derive(Event)]
+pub struct ItemActionEvent {
+    pub entity: Entity,
+    pub action: ItemAction
+}
+
+/// What came of an `ItemActionEvent`.
+#[derive(Event, Debug, Clone, Copy)]
+pub enum InventoryEvent {
+    PickedUp { holder: Entity, item: Item, pos: IVec3 },
+    Dropped { holder: Entity, item: Item, pos: IVec3 },
+    TooHeavy { holder: Entity, item: Item },
+    NothingHere { holder: Entity },
+}
+
+fn item_bundle(item: Item) -> (Item, Appearance, Gravity) {
+    (item, item.kind.stats().appearance, Gravity)
+}
+
+pub fn spawn_item(
+    commands: &mut Commands,
+    item: Item,
+    pos: IVec3,
+    event: &mut EventWriter<AsciiAddEvent>,
+) -> Entity {
+    let entity = commands.spawn((AsciiTile {pos}, item_bundle(item))).id();
+    event.send(AsciiAddEvent {
+        entity,
+        pos
+    });
+    entity
+}
+
+/// A stack carried by `holder` and not on the grid. Adding it to the holder's `Inventory` is up to the caller.
+pub fn spawn_held_item(commands: &mut Commands, holder: Entity, item: Item) -> Entity {
+    let entity = commands.spawn(item_bundle(item)).id();
+    commands.entity(holder).add_child(entity);
+    entity
+}
+
+/// Puts every carried stack on the ground at `pos`, e.g. when the holder dies.
+pub fn drop_everything(
+    commands: &mut Commands,
+    inventory: &mut Inventory,
+    pos: IVec3,
+    event: &mut EventWriter<AsciiAddEvent>,
+) {
+    for held in inventory.items.drain(..) {
+        commands.entity(held).remove_parent().insert(AsciiTile {pos});
+        event.send(AsciiAddEvent {
+            entity: held,
+            pos
+        });
+    }
+}
+
+/// Drops rocks, sticks and the odd apple on the dry surface of a freshly generated chunk,
+/// one stack per 128 columns.
+pub fn scatter_items(
+    commands: &mut Commands,
+    grid: &Grid,
+    gen: &WorldGen,
+    chunk: IVec3,
+    rng: &mut impl Rng,
+    event: &mut EventWriter<AsciiAddEvent>,
+) {
+    let origin = chunk * CHUNK_SIZE;
+    for _ in 0..(CHUNK_SIZE.x * CHUNK_SIZE.y / 128).max(1) {
+        let x = origin.x + rng.gen_range(0..CHUNK_SIZE.x);
+        let y = origin.y + rng.gen_range(0..CHUNK_SIZE.y);
+        let pos = gen.surface(grid.depth(), x, y);
+        if chunk_of(pos) != chunk || grid.terrain(pos).map_or(true, |t| t.is_solid() || t == Terrain::Water) {
+            continue;
+        }
+        let kind = [ItemKind::Rock, ItemKind::Rock, ItemKind::Stick, ItemKind::Stick, ItemKind::Apple][rng.gen_range(0..5)];
+        spawn_item(commands, Item::new(kind, rng.gen_range(1..=3)), pos, event);
+    }
+}
+
+fn pick_up(
+    commands: &mut Commands,
+    holder: Entity,
+    pos: IVec3,
+    grid: &mut Grid,
+    inventory: &mut Inventory,
+    items: &mut Query<&mut Item>,
+    ground: &Query<(), (With<Item>, With<AsciiTile>)>,
+    removed: &mut EventWriter<AsciiRemoveEvent>,
+    events: &mut EventWriter<InventoryEvent>,
+) {
+    let on_ground = grid.occupants(pos).iter()
+        .filter(|e| ground.contains(**e))
+        .copied()
+        .collect::<Vec<_>>();
+    if on_ground.is_empty() {
+        events.send(InventoryEvent::NothingHere { holder });
+        return;
+    }
+    let (mut weight, mut volume) = inventory.load(items);
+    for ground_entity in on_ground {
+        let item = *items.get(ground_entity).unwrap();
+        let stats = item.kind.stats();
+        let by_weight = ((inventory.max_weight - weight) / stats.weight).floor().max(0.) as u32;
+        let by_volume = ((inventory.max_volume - volume) / stats.volume).floor().max(0.) as u32;
+        let taken = item.count.min(by_weight).min(by_volume);
+        if taken == 0 {
+            events.send(InventoryEvent::TooHeavy { holder, item });
+            continue;
+        }
+        weight += stats.weight * taken as f32;
+        volume += stats.volume * taken as f32;
+
+        let mut remaining = taken;
+        for held in inventory.items.iter() {
+            if let Ok(mut stack) = items.get_mut(*held) {
+                if stack.kind == item.kind && stack.count < stats.max_stack {
+                    let n = remaining.min(stats.max_stack - stack.count);
+                    stack.count += n;
+                    remaining -= n;
+                }
+            }
+        }
+        if remaining > 0 && remaining == item.count {
+            // The whole stack moves into the inventory as it is.
+            commands.entity(ground_entity).remove::<AsciiTile>().set_parent(holder);
+            grid.remove(ground_entity);
+            removed.send(AsciiRemoveEvent::new(ground_entity, pos));
+            inventory.items.push(ground_entity);
+        } else {
+            while remaining > 0 {
+                let n = remaining.min(stats.max_stack);
+                inventory.items.push(spawn_held_item(commands, holder, Item::new(item.kind, n)));
+                remaining -= n;
+            }
+            let mut left = items.get_mut(ground_entity).unwrap();
+            left.count -= taken;
+            if left.count == 0 {
+                grid.remove(ground_entity);
+                removed.send(AsciiRemoveEvent::new(ground_entity, pos));
+                commands.entity(ground_entity).despawn_recursive();
+            }
+        }
+        events.send(InventoryEvent::PickedUp { holder, item: Item::new(item.kind, taken), pos });
+    }
+}
+
+fn drop_item(
+    commands: &mut Commands,
+    holder: Entity,
+    index: usize,
+    pos: IVec3,
+    grid: &mut Grid,
+    inventory: &mut Inventory,
+    items: &mut Query<&mut Item>,
+    ground: &Query<(), (With<Item>, With<AsciiTile>)>,
+    add: &mut EventWriter<AsciiAddEvent>,
+    events: &mut EventWriter<InventoryEvent>,
+) {
+    if index >= inventory.items.len() {
+        return;
+    }
+    let held = inventory.items.remove(index);
+    let Ok(item) = items.get(held).copied() else {
+        return;
+    };
+    let stats = item.kind.stats();
+    let mut remaining = item.count;
+    for other in grid.occupants(pos).iter().filter(|e| ground.contains(**e)) {
+        if let Ok(mut stack) = items.get_mut(*other) {
+            if stack.kind == item.kind && stack.count < stats.max_stack {
+                let n = remaining.min(stats.max_stack - stack.count);
+                stack.count += n;
+                remaining -= n;
+            }
+        }
+    }
+    if remaining == 0 {
+        commands.entity(held).despawn_recursive();
+    } else {
+        items.get_mut(held).unwrap().count = remaining;
+        commands.entity(held).remove_parent().insert(AsciiTile {pos});
+        grid.insert(held, pos);
+        add.send(AsciiAddEvent {
+            entity: held,
+            pos
+        });
+    }
+    events.send(InventoryEvent::Dropped { holder, item, pos });
+}
+
+fn handle_item_actions(
+    mut commands: Commands,
+    mut actions: EventReader<ItemActionEvent>,
+    mut grid: ResMut<Grid>,
+    mut holders: Query<&mut Inventory>,
+    mut items: Query<&mut Item>,
+    ground: Query<(), (With<Item>, With<AsciiTile>)>,
+    mut add: EventWriter<AsciiAddEvent>,
+    mut removed: EventWriter<AsciiRemoveEvent>,
+    mut events: EventWriter<InventoryEvent>,
+) {
+    for ev in actions.read() {
+        let Some(pos) = grid.position_of(ev.entity) else {
+            continue;
+        };
+        let Ok(mut inventory) = holders.get_mut(ev.entity) else {
+            continue;
+        };
+        match ev.action {
+            ItemAction::PickUp => pick_up(&mut commands, ev.entity, pos, &mut grid, &mut inventory, &mut items, &ground, &mut removed, &mut events),
+            ItemAction::Drop(index) => drop_item(&mut commands, ev.entity, index, pos, &mut grid, &mut inventory, &mut items, &ground, &mut add, &mut events),
+        }
+    }
+}
+
+pub struct ItemPlugin;
+impl Plugin for ItemPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_event::<ItemActionEvent>()
+            .add_event::<InventoryEvent>()
+            .add_systems(Tick, handle_item_actions.in_set(TickSet::Players).after(apply_commands));
+    }
+}
@@ -0,0 +1,72 @@
+pub mod ascii_world;
+pub mod ascii_render;
+pub mod debug;
+pub mod player;
+pub mod living_entity;
+pub mod world_map;
+pub mod ui;
+pub mod save;
+pub mod net;
+pub mod config;
+pub mod input;
+pub mod pathfinding;
+pub mod fov;
+pub mod lighting;
+pub mod turn;
+pub mod item;
+pub mod inventory_screen;
+pub mod combat;
+pub mod terraform;
+pub mod message_log;
+pub mod chunk;
+pub mod headless;
+pub mod terminal;
+pub mod rng;
+pub mod replay;
+
+use std::time::Duration;
+use bevy::app::{PluginGroupBuilder, ScheduleRunnerPlugin};
+use bevy::log::LogPlugin;
+use bevy::prelude::*;
+
+#[derive(States, Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub enum MainState {
+    #[default]
+    MainMenu,
+    InGame
+}
+
+/// Everything that runs the world, and nothing that draws it or reads input, so it
+/// runs just as well on a machine without a GPU.
+pub struct SimulationPlugins;
+impl PluginGroup for SimulationPlugins {
+    fn build(self) -> PluginGroupBuilder {
+        PluginGroupBuilder::start::<Self>()
+            .add(rng::RngPlugin)
+            .add(ascii_world::AsciiWorldPlugin)
+            .add(chunk::ChunkPlugin)
+            .add(world_map::WorldMapPlugin)
+            .add(turn::TurnPlugin)
+            .add(pathfinding::PathfindingPlugin)
+            .add(fov::FovPlugin)
+            .add(item::ItemPlugin)
+            .add(combat::CombatPlugin)
+            .add(terraform::TerraformPlugin)
+            .add(living_entity::LivingEntityPlugin)
+            .add(save::SavePlugin)
+            .add(net::NetPlugin)
+            .add(replay::ReplayPlugin)
+    }
+}
+
+/// An app without a window, running the simulation a frame every `frame`, or as fast as
+/// it can when that is zero.
+pub fn headless_app(frame: Duration) -> App {
+    let mut app = App::new();
+    app
+        .init_state::<MainState>()
+        .add_plugins(MinimalPlugins.set(ScheduleRunnerPlugin::run_loop(frame)))
+        .add_plugins(LogPlugin::default())
+        .add_plugins(SimulationPlugins);
+    app
+}
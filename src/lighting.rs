@@ -0,0 +1,136 @@
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use serde::{Deserialize, Serialize};
+use crate::ascii_world::{AsciiTile, AsciiWorldSet, Grid, TerrainChangedEvent};
+use crate::chunk::{ChunkEvent, CHUNK_SIZE};
+use crate::fov::compute_fov;
+
+/// Light level of cells reached by neither the sky nor a light source.
+const AMBIENT: f32 = 0.06;
+
+/// Lights the cells it can see, fading out towards `radius`.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct LightSource {
+    pub radius: u32,
+    pub color: Color,
+    pub intensity: f32
+}
+
+/// Light reaching every cell: full daylight down each column until the first solid cell,
+/// plus the light sources, which are recomputed whenever one of them moves or changes.
+#[derive(Resource, Default)]
+pub struct LightMap {
+    revision: u64,
+    /// Layer of the highest solid cell of every loaded column that has one, daylight reaches
+    /// down to it.
+    sky: HashMap<IVec2, i32>,
+    lights: HashMap<IVec3, [f32; 3]>,
+    /// Cells whose light changed this frame.
+    pub dirty: Vec<IVec3>,
+}
+impl LightMap {
+    pub fn light(&self, pos: IVec3) -> Color {
+        let base = if self.sky.get(&pos.truncate()).map_or(true, |top| pos.z >= *top) { 1. } else { AMBIENT };
+        let [r, g, b] = self.lights.get(&pos).copied().unwrap_or_default();
+        Color::rgb((base + r).min(1.), (base + g).min(1.), (base + b).min(1.))
+    }
+    /// `color` as it looks under the light at `pos`.
+    pub fn apply(&self, pos: IVec3, color: Color) -> Color {
+        let light = self.light(pos);
+        Color::rgba(color.r() * light.r(), color.g() * light.g(), color.b() * light.b(), color.a())
+    }
+    /// Looks for where daylight stops in `column` again, returning the layers lit differently now.
+    fn update_column(&mut self, grid: &Grid, column: IVec2) -> std::ops::RangeInclusive<i32> {
+        let old = self.sky.get(&column).copied().unwrap_or(-1);
+        let top = (0..grid.depth() as i32).rev()
+            .find(|z| grid.terrain(column.extend(*z)).is_some_and(|t| t.is_solid()));
+        let new = match top {
+            Some(top) => {
+                self.sky.insert(column, top);
+                top
+            }
+            None => {
+                self.sky.remove(&column);
+                -1
+            }
+        };
+        if old == new {
+            return 1..=0;
+        }
+        old.min(new).max(0)..=old.max(new)
+    }
+}
+
+fn update_lighting(
+    grid: Res<Grid>,
+    mut light_map: ResMut<LightMap>,
+    mut chunks: EventReader<ChunkEvent>,
+    mut terrain: EventReader<TerrainChangedEvent>,
+    lights: Query<(&AsciiTile, &LightSource)>,
+    changed: Query<(), (With<LightSource>, Or<(Changed<AsciiTile>, Changed<LightSource>)>)>,
+    mut removed: RemovedComponents<LightSource>,
+) {
+    light_map.dirty.clear();
+    // Views repaint whole chunks as they load, only reshaped columns need repainting here.
+    for ev in chunks.read() {
+        let (ChunkEvent::Loaded(chunk) | ChunkEvent::Unloaded(chunk)) = *ev;
+        let origin = chunk.truncate() * CHUNK_SIZE.truncate();
+        for y in 0..CHUNK_SIZE.y {
+            for x in 0..CHUNK_SIZE.x {
+                light_map.update_column(&grid, origin + IVec2::new(x, y));
+            }
+        }
+    }
+    for ev in terrain.read() {
+        let column = ev.pos.truncate();
+        let layers = light_map.update_column(&grid, column);
+        light_map.dirty.extend(layers.map(|z| column.extend(z)));
+    }
+    // Only terrain within reach of a light changes what it lights.
+    let revision = lights.iter()
+        .map(|(tile, light)| {
+            let reach = IVec2::splat(light.radius as i32);
+            grid.revision_in((tile.pos.truncate() - reach).extend(0), (tile.pos.truncate() + reach).extend(tile.pos.z))
+        })
+        .max()
+        .unwrap_or_default();
+    let terrain_changed = light_map.revision != revision;
+    light_map.revision = revision;
+    let lights_removed = removed.read().count() > 0;
+    if !terrain_changed && !lights_removed && changed.is_empty() {
+        return;
+    }
+    let mut lit: HashMap<IVec3, [f32; 3]> = HashMap::new();
+    for (tile, light) in lights.iter() {
+        for pos in compute_fov(&grid, tile.pos, light.radius) {
+            let distance = pos.as_vec3().distance(tile.pos.as_vec3());
+            let falloff = (1. - distance / (light.radius as f32 + 1.)).max(0.) * light.intensity;
+            if falloff <= 0. {
+                continue;
+            }
+            let cell = lit.entry(pos).or_default();
+            cell[0] += light.color.r() * falloff;
+            cell[1] += light.color.g() * falloff;
+            cell[2] += light.color.b() * falloff;
+        }
+    }
+    let mut dirty = light_map.lights.iter()
+        .filter(|(pos, old)| lit.get(*pos) != Some(*old))
+        .map(|(pos, _)| *pos)
+        .collect::<Vec<_>>();
+    dirty.extend(lit.keys().filter(|pos| !light_map.lights.contains_key(*pos)).copied());
+    light_map.dirty.extend(dirty);
+    light_map.lights = lit;
+}
+
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LightingSet;
+
+pub struct LightingPlugin;
+impl Plugin for LightingPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<LightMap>()
+            .add_systems(Update, update_lighting.in_set(LightingSet).after(AsciiWorldSet));
+    }
+}
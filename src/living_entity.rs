@@ -1,5 +1,15 @@
 use bevy::prelude::*;
-use crate::ascii_world::{AsciiMoveEvent, AsciiTile, WorldSettings};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use crate::ascii_world::{Appearance, AsciiAddEvent, AsciiTile, Blocking, Gravity, Grid, GridMover, Terrain};
+use crate::chunk::{chunk_of, CHUNK_SIZE};
+use crate::combat::{in_reach, line_of_fire, Attack, AttackEvent, FireEvent, RangedAttack};
+use crate::fov::Viewshed;
+use crate::item::{spawn_held_item, Inventory, Item, ItemKind};
+use crate::pathfinding::PathCache;
+use crate::rng::GameRng;
+use crate::turn::{Actor, Tick, TickSet, NORMAL_SPEED};
+use crate::world_map::WorldGen;
 
 #[derive(Component)]
 pub struct Movement {
@@ -7,9 +17,319 @@ pub struct Movement {
     pub d: Vec3
 }
 
+#[derive(Component, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Health {
+    pub current: i32,
+    pub max: i32
+}
+impl Health {
+    pub fn new(max: i32) -> Self {
+        Self {
+            current: max,
+            max
+        }
+    }
+    pub fn fraction(&self) -> f32 {
+        self.current as f32 / self.max.max(1) as f32
+    }
+}
+
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Faction {
+    Player,
+    Wildlife,
+    Hostile,
+}
+impl Faction {
+    /// Whether members of this faction attack members of `other` on sight.
+    pub fn hostile_to(&self, other: Faction) -> bool {
+        matches!((self, other), (Faction::Player, Faction::Hostile) | (Faction::Hostile, Faction::Player))
+    }
+    /// Whether members of this faction run from members of `other` on sight.
+    pub fn fears(&self, other: Faction) -> bool {
+        matches!((self, other), (Faction::Wildlife, Faction::Player) | (Faction::Wildlife, Faction::Hostile))
+    }
+    /// Whether bumping into or shooting at members of `other` attacks them. Players hunt
+    /// wildlife too, but never each other.
+    pub fn attacks(&self, other: Faction) -> bool {
+        self.hostile_to(other) || matches!((self, other), (Faction::Player, Faction::Wildlife))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AiState {
+    Idle,
+    Wander,
+    Chase(Entity),
+    Flee(Entity),
+}
+
+/// The kinds of creatures that can be spawned, see `Archetype::stats`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Archetype {
+    Rat,
+    Deer,
+    Goblin,
+}
+pub struct ArchetypeStats {
+    pub name: &'static str,
+    pub appearance: Appearance,
+    pub health: i32,
+    pub faction: Faction,
+    /// Radius of the creature's field of view.
+    pub sight: u32,
+    /// Energy gained per tick, see `turn::Actor`.
+    pub speed: i32,
+    /// Health fraction below which the creature runs instead of fighting.
+    pub flee_below: f32,
+    pub attack: Attack,
+    pub ranged: Option<RangedAttack>,
+}
+impl Archetype {
+    pub const ALL: [Archetype; 3] = [Archetype::Rat, Archetype::Deer, Archetype::Goblin];
+
+    pub fn stats(&self) -> ArchetypeStats {
+        match self {
+            Archetype::Rat => ArchetypeStats {
+                name: "rat",
+                appearance: Appearance { glyph: 'r', fg: Color::rgb(0.6, 0.5, 0.4), bg: Color::NONE, priority: 50 },
+                health: 4,
+                faction: Faction::Hostile,
+                sight: 6,
+                speed: NORMAL_SPEED,
+                flee_below: 0.5,
+                attack: Attack { damage: 1, accuracy: 0.7 },
+                ranged: None,
+            },
+            Archetype::Deer => ArchetypeStats {
+                name: "deer",
+                appearance: Appearance { glyph: 'd', fg: Color::rgb(0.8, 0.6, 0.3), bg: Color::NONE, priority: 50 },
+                health: 8,
+                faction: Faction::Wildlife,
+                sight: 10,
+                speed: NORMAL_SPEED * 3 / 2,
+                flee_below: 1.,
+                attack: Attack { damage: 2, accuracy: 0.6 },
+                ranged: None,
+            },
+            Archetype::Goblin => ArchetypeStats {
+                name: "goblin",
+                appearance: Appearance { glyph: 'g', fg: Color::LIME_GREEN, bg: Color::NONE, priority: 50 },
+                health: 12,
+                faction: Faction::Hostile,
+                sight: 8,
+                speed: NORMAL_SPEED * 4 / 5,
+                flee_below: 0.25,
+                attack: Attack { damage: 3, accuracy: 0.7 },
+                ranged: Some(RangedAttack { damage: 2, range: 6 }),
+            },
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct Creature {
+    pub archetype: Archetype,
+    pub state: AiState,
+}
+impl Creature {
+    pub fn new(archetype: Archetype) -> Self {
+        Self {
+            archetype,
+            state: AiState::Idle
+        }
+    }
+}
+
+pub fn spawn_creature(
+    commands: &mut Commands,
+    archetype: Archetype,
+    pos: IVec3,
+    rng: &mut impl Rng,
+    event: &mut EventWriter<AsciiAddEvent>,
+) -> Entity {
+    let stats = archetype.stats();
+    let entity = commands.spawn((
+        AsciiTile {pos},
+        Creature::new(archetype),
+        Viewshed::new(stats.sight),
+        Actor::new(stats.speed),
+        stats.appearance,
+        Health::new(stats.health),
+        stats.faction,
+        stats.attack,
+        Blocking,
+        Gravity,
+    )).id();
+    if let Some(ranged) = stats.ranged {
+        commands.entity(entity).insert(ranged);
+    }
+    let mut inventory = Inventory::new(stats.health as f32, stats.health as f32);
+    if archetype == Archetype::Goblin {
+        let coins = rng.gen_range(1..=10);
+        inventory.items.push(spawn_held_item(commands, entity, Item::new(ItemKind::Coin, coins)));
+    }
+    commands.entity(entity).insert(inventory);
+    event.send(AsciiAddEvent {
+        entity,
+        pos
+    });
+    entity
+}
+
+/// Scatters creatures over the dry surface of a freshly generated chunk, one per 256 columns.
+pub fn populate(
+    commands: &mut Commands,
+    grid: &Grid,
+    gen: &WorldGen,
+    chunk: IVec3,
+    rng: &mut impl Rng,
+    event: &mut EventWriter<AsciiAddEvent>,
+) {
+    let origin = chunk * CHUNK_SIZE;
+    let count = (CHUNK_SIZE.x * CHUNK_SIZE.y / 256).max(1);
+    let mut spawned = 0;
+    for _ in 0..count * 8 {
+        if spawned == count {
+            break;
+        }
+        let x = origin.x + rng.gen_range(0..CHUNK_SIZE.x);
+        let y = origin.y + rng.gen_range(0..CHUNK_SIZE.y);
+        // Every chunk of the column gets populated, each only where the surface lies in it.
+        let pos = gen.surface(grid.depth(), x, y);
+        if chunk_of(pos) != chunk || grid.is_occupied(pos) || grid.terrain(pos).map_or(true, |t| t.is_solid() || t == Terrain::Water) {
+            continue;
+        }
+        let archetype = Archetype::ALL[rng.gen_range(0..Archetype::ALL.len())];
+        spawn_creature(commands, archetype, pos, rng, event);
+        spawned += 1;
+    }
+}
+
+/// Picks each creature's state from what it can see.
+fn think(
+    mut creatures: Query<(Entity, &AsciiTile, &Health, &Viewshed, &mut Creature)>,
+    others: Query<(Entity, &AsciiTile, &Faction)>,
+    mut rng: ResMut<GameRng>,
+) {
+    for (entity, tile, health, viewshed, mut creature) in creatures.iter_mut() {
+        let stats = creature.archetype.stats();
+        let nearest = others.iter()
+            .filter(|(other, other_tile, faction)| {
+                *other != entity
+                    && (stats.faction.fears(**faction) || stats.faction.hostile_to(**faction))
+                    && viewshed.can_see(other_tile.pos)
+            })
+            .map(|(other, other_tile, faction)| {
+                let d = (other_tile.pos - tile.pos).abs().max_element() as u32;
+                (other, *faction, d)
+            })
+            .min_by_key(|(_, _, d)| *d);
+        creature.state = match nearest {
+            Some((other, faction, _)) if stats.faction.fears(faction) || health.fraction() < stats.flee_below => AiState::Flee(other),
+            Some((other, _, _)) => AiState::Chase(other),
+            None => match creature.state {
+                AiState::Chase(_) | AiState::Flee(_) => AiState::Idle,
+                AiState::Idle if rng.gen_bool(0.05) => AiState::Wander,
+                AiState::Wander if rng.gen_bool(0.05) => AiState::Idle,
+                state => state,
+            },
+        };
+    }
+}
+
+/// Tries `dir`, then the two directions next to it, so creatures slide around corners.
+fn step(mover: &mut GridMover, entity: Entity, dir: IVec2) -> bool {
+    if dir == IVec2::ZERO {
+        return false;
+    }
+    let left = IVec2::new(dir.x - dir.y, dir.x + dir.y).clamp(IVec2::NEG_ONE, IVec2::ONE);
+    let right = IVec2::new(dir.x + dir.y, dir.y - dir.x).clamp(IVec2::NEG_ONE, IVec2::ONE);
+    [dir, left, right].iter().any(|d| mover.try_move(entity, d.extend(0)).is_ok())
+}
+
+/// Steps to `next` when it is a neighbouring cell, climbing when it is on another layer.
+fn step_to(mover: &mut GridMover, entity: Entity, pos: IVec3, next: IVec3) -> bool {
+    let delta = next - pos;
+    if delta.z != 0 {
+        mover.try_climb(entity, delta.z > 0).is_ok()
+    } else {
+        mover.try_move(entity, delta).is_ok()
+    }
+}
+
+/// Lets every creature whose turn it is act on its state. Standing still takes a turn too.
+/// Chasing creatures attack once they are next to their target, or shoot when they can.
+fn act(
+    mut creatures: Query<(Entity, &Creature, &mut Actor)>,
+    mut mover: GridMover,
+    mut paths: ResMut<PathCache>,
+    mut attacks: EventWriter<AttackEvent>,
+    mut fire: EventWriter<FireEvent>,
+    mut rng: ResMut<GameRng>,
+) {
+    for (entity, creature, mut actor) in creatures.iter_mut() {
+        if !actor.ready() {
+            continue;
+        }
+        actor.spend();
+        let Some(pos) = mover.grid().position_of(entity) else {
+            continue;
+        };
+        let stats = creature.archetype.stats();
+        let sight = stats.sight;
+        match creature.state {
+            AiState::Idle => {}
+            AiState::Wander => {
+                step(&mut mover, entity, IVec2::new(rng.gen_range(-1..=1), rng.gen_range(-1..=1)));
+            }
+            AiState::Chase(other) | AiState::Flee(other) => {
+                let Some(other_pos) = mover.grid().position_of(other) else {
+                    continue;
+                };
+                let offset = other_pos - pos;
+                let distance = offset.abs().max_element() as u32;
+                if let AiState::Chase(target) = creature.state {
+                    if in_reach(mover.grid(), pos, other_pos) {
+                        attacks.send(AttackEvent {
+                            attacker: entity,
+                            target
+                        });
+                        continue;
+                    }
+                    let can_shoot = stats.ranged.is_some_and(|ranged| distance <= ranged.range)
+                        && line_of_fire(mover.grid(), pos, other_pos);
+                    if can_shoot && rng.gen_bool(0.5) {
+                        fire.send(FireEvent {
+                            shooter: entity,
+                            target: other_pos
+                        });
+                        continue;
+                    }
+                }
+                let map = paths.dijkstra(mover.grid(), other, other_pos, sight * 4);
+                let next = if matches!(creature.state, AiState::Chase(_)) {
+                    map.downhill(mover.grid(), pos)
+                } else {
+                    map.uphill(mover.grid(), pos)
+                };
+                let moved = next.is_some_and(|next| step_to(&mut mover, entity, pos, next));
+                if !moved {
+                    let dir = offset.truncate().signum();
+                    step(&mut mover, entity, if matches!(creature.state, AiState::Chase(_)) { dir } else { -dir });
+                }
+            }
+        }
+    }
+}
+
 pub struct LivingEntityPlugin;
 impl Plugin for LivingEntityPlugin {
     fn build(&self, app: &mut App) {
-
+        app
+            .add_systems(Tick, (
+                think,
+                act
+            ).chain().in_set(TickSet::Npcs));
     }
-}
\ No newline at end of file
+}
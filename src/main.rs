@@ -1,45 +1,187 @@
-mod ascii_world;
-mod ascii_render;
-mod debug;
-mod player;
-mod living_entity;
-mod world_map;
-mod ui;
-
+use std::path::PathBuf;
+use std::time::Duration;
+use bevy::app::{AppExit, ScheduleRunnerPlugin};
+use bevy::input::InputPlugin;
 use bevy::prelude::*;
 use bevy::window::WindowResolution;
+use uahmt::*;
 
 fn setup(mut commands: Commands) {
 
 }
 
-#[derive(States, Debug, Clone, PartialEq, Eq, Hash, Default)]
-enum MainState {
-    #[default]
-    MainMenu,
-    InGame
+const USAGE: &str = "usage: uahmt [--server] [--headless] [--terminal] [--address ADDRESS] [--load PATH] [--name NAME] [--seed SEED] [--ticks N] [--record] [--replay PATH]";
+
+struct Args {
+    server: bool,
+    headless: bool,
+    terminal: bool,
+    address: Option<String>,
+    load: Option<PathBuf>,
+    name: Option<String>,
+    seed: Option<String>,
+    ticks: Option<u64>,
+    record: bool,
+    replay: Option<PathBuf>,
+}
+/// The arguments, or the first one that isn't known.
+fn parse_args() -> Result<Args, String> {
+    let mut args = Args {
+        server: false,
+        headless: false,
+        terminal: false,
+        address: None,
+        load: None,
+        name: None,
+        seed: None,
+        ticks: None,
+        record: false,
+        replay: None,
+    };
+    let mut iter = std::env::args().skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--server" => args.server = true,
+            "--headless" => args.headless = true,
+            "--terminal" => args.terminal = true,
+            "--address" => args.address = iter.next(),
+            "--load" => args.load = iter.next().map(PathBuf::from),
+            "--name" => args.name = iter.next(),
+            "--seed" => args.seed = iter.next(),
+            "--ticks" => args.ticks = iter.next().and_then(|n| n.parse().ok()),
+            "--record" => args.record = true,
+            "--replay" => args.replay = iter.next().map(PathBuf::from),
+            _ => return Err(arg),
+        }
+    }
+    Ok(args)
+}
+
+fn server(address: String) {
+    headless_app(Duration::from_secs_f64(1. / 60.))
+        .insert_resource(net::NetMode::Server)
+        .insert_resource(net::NetAddress(address))
+        .run();
+}
+
+fn run_headless(args: Args) {
+    let mut app = headless_app(Duration::from_secs_f64(1. / 60.));
+    if args.record {
+        app.init_resource::<replay::Recorder>();
+    }
+    app
+        .insert_resource(headless::HeadlessSettings {
+            load: args.load,
+            name: args.name,
+            seed: args.seed,
+            ticks: args.ticks,
+        })
+        .add_plugins(player::PlayerPlugin)
+        .add_plugins(headless::HeadlessPlugin)
+        .run();
+}
+
+/// Plays a recording back as fast as it goes, the recorded frame times stand in for real ones.
+fn run_replay(path: PathBuf) {
+    let mut app = headless_app(Duration::ZERO);
+    match replay::read_replay(&path) {
+        Ok(replay) => {
+            app
+                .insert_resource(replay::Replayer::new(replay))
+                .add_plugins(player::PlayerPlugin);
+        }
+        Err(e) => {
+            error!("failed to read replay {}: {}", path.display(), e);
+            app.world.send_event(AppExit);
+        }
+    }
+    app.run();
+}
+
+/// Plays in the terminal instead of a window. There is no log output, it would run
+/// through the picture.
+fn run_terminal(address: String, record: bool) {
+    let mut app = App::new();
+    if record {
+        app.init_resource::<replay::Recorder>();
+    }
+    app
+        .init_state::<MainState>()
+        .insert_resource(net::NetAddress(address))
+        .add_plugins(MinimalPlugins.set(ScheduleRunnerPlugin::run_loop(Duration::from_secs_f64(1. / 60.))))
+        .add_plugins(InputPlugin)
+        .add_plugins(input::InputMapPlugin)
+        .add_plugins(lighting::LightingPlugin)
+        .add_plugins(terminal::TerminalPlugin)
+        .add_plugins(SimulationPlugins)
+        .add_plugins(player::PlayerPlugin)
+        .run();
 }
 
 #[bevy_main]
 fn main() {
-    App::new()
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(arg) => {
+            // Nothing is logged yet, this goes straight to the terminal.
+            eprintln!("unknown argument {}\n{}", arg, USAGE);
+            std::process::exit(2);
+        }
+    };
+    let address = args.address.clone().unwrap_or_else(|| String::from(net::DEFAULT_ADDRESS));
+    if args.server {
+        server(address);
+        return;
+    }
+    if let Some(path) = args.replay {
+        run_replay(path);
+        return;
+    }
+    if args.terminal {
+        run_terminal(address, args.record);
+        return;
+    }
+    if args.headless {
+        run_headless(args);
+        return;
+    }
+    let loaded = config::load_config();
+    let config = loaded.as_ref().cloned().unwrap_or_default();
+    let [width, height] = config.resolution;
+    let mut app = App::new();
+    if args.record {
+        app.init_resource::<replay::Recorder>();
+    }
+    app
         .init_state::<MainState>()
+        .insert_resource(net::NetAddress(address))
         .add_plugins(DefaultPlugins
             .set(WindowPlugin {
                 primary_window: Some(Window {
-                    resolution: WindowResolution::new(1920., 1080.),
+                    resolution: WindowResolution::new(width as f32, height as f32),
+                    mode: config.window_mode(),
+                    present_mode: config.present_mode(),
                     title: String::from("Uahmt"),
                     ..default()
                 }),
                 ..default()
             })
-        )
-        .add_plugins(ascii_world::AsciiWorldPlugin)
+        );
+    // Reported once the log plugin is up.
+    if let Err(e) = loaded {
+        error!("ignoring malformed {}: {}", config::CONFIG_PATH, e);
+    }
+    app
+        .insert_resource(config)
+        .add_plugins(config::ConfigPlugin)
+        .add_plugins(input::InputMapPlugin)
         .add_plugins(ascii_render::AsciiRenderPlugin)
+        .add_plugins(lighting::LightingPlugin)
+        .add_plugins(inventory_screen::InventoryScreenPlugin)
+        .add_plugins(message_log::MessageLogPlugin)
         .add_plugins(ui::UiPlugin)
-        .add_plugins(world_map::WorldMapPlugin)
+        .add_plugins(SimulationPlugins)
         .add_plugins(player::PlayerPlugin)
-        .add_plugins(living_entity::LivingEntityPlugin)
         .add_plugins(debug::DebugPlugin)
         .add_systems(Startup, setup)
         .run();
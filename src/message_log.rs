@@ -0,0 +1,369 @@
+use bevy::math::vec3;
+use bevy::prelude::*;
+use bevy::window::{PrimaryWindow, WindowResized};
+use bevy_fast_tilemap::Map;
+use crate::ascii_render::{AsciiAtlas, UserData, TILE_SIZE};
+use crate::ascii_world::{BlockReason, FallEvent, MoveRejectedEvent};
+use crate::combat::{handle_deaths, move_projectiles, CombatEvent};
+use crate::input::{Action, ActionState, ActiveContext, InputContext};
+use crate::item::InventoryEvent;
+use crate::living_entity::{Creature, Faction};
+use crate::MainState;
+use crate::player::PlayerMarker;
+use crate::terraform::{BuildSelection, TerraformEvent};
+use crate::turn::{Tick, TickSet};
+use crate::ui::{blank_map, spawn_panel, write_row};
+
+/// Lines of the log shown at the bottom of the screen.
+const PANEL_ROWS: u32 = 5;
+const HISTORY_SIZE: UVec2 = UVec2::new(72, 32);
+/// Entries kept for the history viewer.
+const MAX_ENTRIES: usize = 500;
+const BACKGROUND: Color = Color::rgba(0., 0., 0., 0.85);
+
+/// A line for the message log, any system may send one.
+#[derive(Event, Debug, Clone)]
+pub struct GameMessage {
+    pub text: String,
+    pub color: Color
+}
+impl GameMessage {
+    pub fn new(text: impl Into<String>, color: Color) -> Self {
+        Self {
+            text: text.into(),
+            color
+        }
+    }
+}
+
+struct LogEntry {
+    text: String,
+    color: Color,
+    count: u32
+}
+impl LogEntry {
+    /// "You hit the rat x3" when the same message came several times in a row.
+    fn line(&self) -> String {
+        if self.count > 1 {
+            format!("{} x{}", self.text, self.count)
+        } else {
+            self.text.clone()
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct MessageLog {
+    entries: Vec<LogEntry>
+}
+impl MessageLog {
+    pub fn push(&mut self, message: &GameMessage) {
+        if let Some(last) = self.entries.last_mut() {
+            if last.text == message.text && last.color == message.color {
+                last.count += 1;
+                return;
+            }
+        }
+        self.entries.push(LogEntry {
+            text: message.text.clone(),
+            color: message.color,
+            count: 1
+        });
+        if self.entries.len() > MAX_ENTRIES {
+            self.entries.remove(0);
+        }
+    }
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+#[derive(Component)]
+struct LogPanel;
+#[derive(Component)]
+struct HistoryViewer {
+    /// Entries scrolled back from the newest.
+    scroll: usize
+}
+
+fn collect_messages(
+    mut messages: EventReader<GameMessage>,
+    mut log: ResMut<MessageLog>,
+) {
+    for message in messages.read() {
+        log.push(message);
+    }
+}
+
+/// "you", "the rat" or "something".
+fn name_of(entity: Entity, players: &Query<(), With<PlayerMarker>>, creatures: &Query<&Creature>) -> String {
+    if players.contains(entity) {
+        String::from("you")
+    } else if let Ok(creature) = creatures.get(entity) {
+        format!("the {}", creature.archetype.stats().name)
+    } else {
+        String::from("something")
+    }
+}
+
+fn capitalize(text: String) -> String {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => text,
+    }
+}
+
+/// Runs in the tick the events happen in, while the dead still have their names.
+fn describe_combat(
+    mut combat: EventReader<CombatEvent>,
+    players: Query<(), With<PlayerMarker>>,
+    creatures: Query<&Creature>,
+    mut messages: EventWriter<GameMessage>,
+) {
+    let name = |entity| name_of(entity, &players, &creatures);
+    for ev in combat.read() {
+        let message = match *ev {
+            CombatEvent::Hit { attacker, target, damage } if players.contains(attacker) =>
+                GameMessage::new(format!("You hit {} for {}", name(target), damage), Color::WHITE),
+            CombatEvent::Hit { attacker, target, damage } if players.contains(target) =>
+                GameMessage::new(capitalize(format!("{} hits you for {}", name(attacker), damage)), Color::RED),
+            CombatEvent::Missed { attacker, target } if players.contains(attacker) =>
+                GameMessage::new(format!("You miss {}", name(target)), Color::GRAY),
+            CombatEvent::Missed { attacker, target } if players.contains(target) =>
+                GameMessage::new(capitalize(format!("{} misses you", name(attacker))), Color::GRAY),
+            CombatEvent::Died { entity, .. } if players.contains(entity) =>
+                GameMessage::new("You die... and wake up where you started", Color::ORANGE_RED),
+            CombatEvent::Died { entity, killer, .. } if players.contains(killer) =>
+                GameMessage::new(format!("You kill {}", name(entity)), Color::YELLOW),
+            _ => continue,
+        };
+        messages.send(message);
+    }
+}
+
+/// Puts what happens to or is done by the local player into words.
+fn describe_events(
+    mut inventory: EventReader<InventoryEvent>,
+    mut terraform: EventReader<TerraformEvent>,
+    mut rejected: EventReader<MoveRejectedEvent>,
+    mut fall: EventReader<FallEvent>,
+    selection: Res<BuildSelection>,
+    players: Query<(), With<PlayerMarker>>,
+    factions: Query<&Faction>,
+    mut messages: EventWriter<GameMessage>,
+) {
+    for ev in inventory.read() {
+        let message = match *ev {
+            InventoryEvent::PickedUp { holder, item, .. } if players.contains(holder) =>
+                GameMessage::new(format!("You pick up {}", item.describe()), Color::WHITE),
+            InventoryEvent::Dropped { holder, item, .. } if players.contains(holder) =>
+                GameMessage::new(format!("You drop {}", item.describe()), Color::WHITE),
+            InventoryEvent::TooHeavy { holder, item } if players.contains(holder) =>
+                GameMessage::new(format!("You can't carry {}", item.describe()), Color::ORANGE),
+            InventoryEvent::NothingHere { holder } if players.contains(holder) =>
+                GameMessage::new("There is nothing here to pick up", Color::GRAY),
+            _ => continue,
+        };
+        messages.send(message);
+    }
+    for ev in terraform.read() {
+        let message = match *ev {
+            TerraformEvent::Dug { entity, .. } if players.contains(entity) =>
+                GameMessage::new("You dig through", Color::WHITE),
+            TerraformEvent::Built { entity, structure, .. } if players.contains(entity) =>
+                GameMessage::new(format!("You build a {}", structure.name()), Color::WHITE),
+            TerraformEvent::CantDig { entity, .. } if players.contains(entity) =>
+                GameMessage::new("There is nothing there you can dig", Color::GRAY),
+            TerraformEvent::CantBuild { entity, .. } if players.contains(entity) =>
+                GameMessage::new("There is no room to build there", Color::GRAY),
+            TerraformEvent::MissingMaterials { entity, structure } if players.contains(entity) => {
+                let materials = structure.materials();
+                GameMessage::new(format!("A {} takes {}", structure.name(), materials.describe()), Color::ORANGE)
+            }
+            _ => continue,
+        };
+        messages.send(message);
+    }
+    for ev in rejected.read() {
+        if !players.contains(ev.entity) {
+            continue;
+        }
+        let message = match ev.reason {
+            BlockReason::ClosedDoor(_) => GameMessage::new("You open the door", Color::WHITE),
+            BlockReason::NoStairs => GameMessage::new("There is nothing to climb here", Color::GRAY),
+            BlockReason::Terrain(_) => GameMessage::new("You bump into a wall", Color::GRAY),
+            // Bumping into what the player attacks is an attack, combat reports it.
+            BlockReason::Entity(other) if factions.get(ev.entity).is_ok_and(|f| factions.get(other).is_ok_and(|o| f.attacks(*o))) => continue,
+            BlockReason::Entity(_) => GameMessage::new("There is someone in the way", Color::GRAY),
+            BlockReason::OutOfBounds => continue,
+        };
+        messages.send(message);
+    }
+    for ev in fall.read() {
+        if players.contains(ev.entity) {
+            let text = if ev.distance == 1 { String::from("You fall a layer") } else { format!("You fall {} layers", ev.distance) };
+            messages.send(GameMessage::new(text, Color::ORANGE));
+        }
+    }
+    if selection.is_changed() && !selection.is_added() {
+        messages.send(GameMessage::new(format!("You will build a {}", selection.0.name()), Color::CYAN));
+    }
+}
+
+fn spawn_log_panel(
+    mut commands: Commands,
+    ascii_atlas: Res<AsciiAtlas>,
+    mut materials: ResMut<Assets<Map<UserData>>>,
+    window: Query<&Window, With<PrimaryWindow>>,
+    camera: Query<Entity, With<Camera>>,
+) {
+    let (Ok(window), Ok(camera)) = (window.get_single(), camera.get_single()) else {
+        return;
+    };
+    let columns = (window.width() / TILE_SIZE) as u32;
+    let map = blank_map(&ascii_atlas, UVec2::new(columns, PANEL_ROWS), BACKGROUND);
+    spawn_panel(&mut commands, &mut materials, camera, map, -101., LogPanel);
+}
+
+/// Keeps the panel docked to the bottom of the window and as wide as it. The camera's
+/// projection scales everything under it, the panel is scaled back to stay glyph sized.
+fn fit_log_panel(
+    ascii_atlas: Res<AsciiAtlas>,
+    mut materials: ResMut<Assets<Map<UserData>>>,
+    mut resized: EventReader<WindowResized>,
+    window: Query<&Window, With<PrimaryWindow>>,
+    camera: Query<&OrthographicProjection, With<Camera>>,
+    mut panel: Query<(&mut Handle<Map<UserData>>, &mut Transform), With<LogPanel>>,
+) {
+    let (Ok(window), Ok(projection), Ok((mut map_handle, mut transform))) = (window.get_single(), camera.get_single(), panel.get_single_mut()) else {
+        return;
+    };
+    let scale = projection.scale;
+    let y = (PANEL_ROWS as f32 * TILE_SIZE - window.height()) / 2. * scale;
+    if transform.translation.y != y || transform.scale.x != scale {
+        transform.translation.y = y;
+        transform.scale = vec3(scale, scale, 1.);
+    }
+    if resized.read().last().is_some() {
+        let columns = (window.width() / TILE_SIZE) as u32;
+        *map_handle = materials.add(blank_map(&ascii_atlas, UVec2::new(columns, PANEL_ROWS), BACKGROUND));
+    }
+}
+
+fn draw_log_panel(
+    log: Res<MessageLog>,
+    mut materials: ResMut<Assets<Map<UserData>>>,
+    panel: Query<Ref<Handle<Map<UserData>>>, With<LogPanel>>,
+) {
+    let Ok(map_handle) = panel.get_single() else {
+        return;
+    };
+    // A new map, for a new panel or after a resize, starts out blank.
+    if !log.is_changed() && !map_handle.is_changed() {
+        return;
+    }
+    let map = materials.get_mut(&*map_handle).unwrap();
+    let shown = log.entries.iter().rev().take(PANEL_ROWS as usize).collect::<Vec<_>>();
+    for row in 0..PANEL_ROWS {
+        // The newest entry sits on the bottom row, older ones fade out above it.
+        let age = (PANEL_ROWS - 1 - row) as usize;
+        match shown.get(age) {
+            Some(entry) => {
+                let fade = 1. - age as f32 * 0.15;
+                let color = Color::rgba(entry.color.r() * fade, entry.color.g() * fade, entry.color.b() * fade, 1.);
+                write_row(map, row, &format!(" {}", entry.line()), color, BACKGROUND);
+            }
+            None => write_row(map, row, "", Color::WHITE, BACKGROUND),
+        }
+    }
+}
+
+fn toggle_history_viewer(
+    mut commands: Commands,
+    actions: Res<ActionState>,
+    ascii_atlas: Res<AsciiAtlas>,
+    mut materials: ResMut<Assets<Map<UserData>>>,
+    mut context: ResMut<ActiveContext>,
+    viewer: Query<Entity, With<HistoryViewer>>,
+    camera: Query<Entity, With<Camera>>,
+) {
+    if let Ok(entity) = viewer.get_single() {
+        if actions.just_pressed(Action::MenuBack) {
+            commands.entity(entity).despawn_recursive();
+            context.0 = InputContext::Game;
+        }
+        return;
+    }
+    if !actions.just_pressed(Action::MessageHistory) {
+        return;
+    }
+    let Ok(camera) = camera.get_single() else {
+        return;
+    };
+    let map = blank_map(&ascii_atlas, HISTORY_SIZE, BACKGROUND);
+    spawn_panel(&mut commands, &mut materials, camera, map, -99., HistoryViewer { scroll: 0 });
+    context.0 = InputContext::Menu;
+}
+
+fn draw_history_viewer(
+    actions: Res<ActionState>,
+    log: Res<MessageLog>,
+    mut materials: ResMut<Assets<Map<UserData>>>,
+    mut viewer: Query<(&Handle<Map<UserData>>, &mut HistoryViewer)>,
+) {
+    let Ok((map_handle, mut viewer)) = viewer.get_single_mut() else {
+        return;
+    };
+    let rows = (HISTORY_SIZE.y - 2) as usize;
+    let max_scroll = log.len().saturating_sub(rows);
+    if actions.just_pressed(Action::MenuUp) {
+        viewer.scroll = (viewer.scroll + 1).min(max_scroll);
+    }
+    if actions.just_pressed(Action::MenuDown) {
+        viewer.scroll = viewer.scroll.saturating_sub(1);
+    }
+    let map = materials.get_mut(map_handle).unwrap();
+    write_row(map, 0, &format!(" Messages ({})", log.len()), Color::WHITE, BACKGROUND);
+    let end = log.len() - viewer.scroll.min(log.len());
+    let start = end.saturating_sub(rows);
+    let shown = &log.entries[start..end];
+    for row in 0..rows {
+        // Bottom aligned like the panel, so the newest entry is right above the hint.
+        let i = (row + shown.len()).checked_sub(rows);
+        match i.and_then(|i| shown.get(i)) {
+            Some(entry) => write_row(map, row as u32 + 1, &format!(" {}", entry.line()), entry.color, BACKGROUND),
+            None => write_row(map, row as u32 + 1, "", Color::WHITE, BACKGROUND),
+        }
+    }
+    write_row(map, HISTORY_SIZE.y - 1, " Up/Down: scroll   Esc: close", Color::GRAY, BACKGROUND);
+}
+
+fn despawn_panels(
+    mut commands: Commands,
+    panels: Query<Entity, Or<(With<LogPanel>, With<HistoryViewer>)>>,
+) {
+    for entity in panels.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+pub struct MessageLogPlugin;
+impl Plugin for MessageLogPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<MessageLog>()
+            .add_event::<GameMessage>()
+            .add_systems(OnEnter(MainState::InGame), spawn_log_panel)
+            .add_systems(OnExit(MainState::InGame), despawn_panels)
+            .add_systems(Tick, describe_combat.in_set(TickSet::Resolve).after(move_projectiles).before(handle_deaths))
+            .add_systems(Update, (
+                describe_events,
+                collect_messages,
+                fit_log_panel,
+                draw_log_panel,
+                toggle_history_viewer,
+                draw_history_viewer
+            ).chain().run_if(in_state(MainState::InGame)));
+    }
+}
@@ -0,0 +1,449 @@
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use crate::ascii_world::{Appearance, AsciiAddEvent, AsciiMoveEvent, AsciiRemoveEvent, AsciiTile, Grid, Terrain, TerrainChangedEvent, WorldSettings};
+use crate::chunk::{ChunkSet, ChunkStore};
+use crate::lighting::LightSource;
+use crate::living_entity::Movement;
+use crate::MainState;
+use crate::player::{spawn_player, PendingCommand, PlayerCommand, PlayerMarker};
+use crate::save::WorldLoadedEvent;
+use crate::world_map::{spawn_point, WorldGen};
+
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1:7777";
+
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NetMode {
+    #[default]
+    Offline,
+    Server,
+    Client
+}
+
+/// Address the server listens on, or the client connects to.
+#[derive(Resource)]
+pub struct NetAddress(pub String);
+impl FromWorld for NetAddress {
+    fn from_world(_world: &mut World) -> Self {
+        Self(String::from(DEFAULT_ADDRESS))
+    }
+}
+
+/// Another client's player, as seen by this client.
+#[derive(Component)]
+pub struct RemotePlayer;
+
+#[derive(Event)]
+pub struct ConnectEvent;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum ServerMessage {
+    Welcome { player: u64, seed: u32, depth: u32 },
+    Add { entity: u64, pos: [i32; 3], player: bool, movement: Option<f32>, #[serde(default)] appearance: Option<Appearance>, #[serde(default)] light: Option<LightSource> },
+    Move { entity: u64, pos: [i32; 3] },
+    Remove { entity: u64 },
+    Terrain { pos: [i32; 3], terrain: Terrain },
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum ClientMessage {
+    Command(PlayerCommand),
+}
+
+/// Non-blocking TCP stream exchanging newline separated RON messages.
+struct Connection {
+    stream: TcpStream,
+    incoming: Vec<u8>,
+    outgoing: Vec<u8>,
+}
+impl Connection {
+    fn new(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+        Ok(Self {
+            stream,
+            incoming: Vec::new(),
+            outgoing: Vec::new(),
+        })
+    }
+    fn send<T: Serialize>(&mut self, message: &T) {
+        match ron::to_string(message) {
+            Ok(line) => {
+                self.outgoing.extend_from_slice(line.as_bytes());
+                self.outgoing.push(b'\n');
+            }
+            Err(e) => error!("failed to encode message: {}", e),
+        }
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        while !self.outgoing.is_empty() {
+            match self.stream.write(&self.outgoing) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(n) => {
+                    self.outgoing.drain(..n);
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+    fn receive<T: DeserializeOwned>(&mut self) -> io::Result<Vec<T>> {
+        let mut buffer = [0u8; 4096];
+        loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+                Ok(n) => self.incoming.extend_from_slice(&buffer[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+        }
+        let mut messages = Vec::new();
+        while let Some(end) = self.incoming.iter().position(|b| *b == b'\n') {
+            let line = self.incoming.drain(..=end).collect::<Vec<_>>();
+            match std::str::from_utf8(&line).map_err(|e| e.to_string())
+                .and_then(|line| ron::from_str(line).map_err(|e| e.to_string())) {
+                Ok(message) => messages.push(message),
+                Err(e) => warn!("dropping malformed message: {}", e),
+            }
+        }
+        Ok(messages)
+    }
+}
+
+struct Client {
+    connection: Connection,
+    player: Entity,
+}
+#[derive(Resource)]
+struct Server {
+    listener: TcpListener,
+    clients: Vec<Client>,
+    /// Cells reshaped since the world was generated, clients regenerate the rest from the seed.
+    terrain: HashMap<IVec3, Terrain>,
+}
+#[derive(Resource)]
+struct ServerConnection {
+    connection: Connection,
+    player: Option<u64>,
+    entities: HashMap<u64, Entity>,
+}
+
+fn start_server(
+    mut commands: Commands,
+    address: Res<NetAddress>,
+) {
+    let listener = TcpListener::bind(&address.0)
+        .and_then(|listener| listener.set_nonblocking(true).map(|_| listener));
+    match listener {
+        Ok(listener) => {
+            info!("listening on {}", address.0);
+            commands.insert_resource(Server {
+                listener,
+                clients: Vec::new(),
+                terrain: HashMap::new()
+            });
+        }
+        Err(e) => error!("failed to listen on {}: {}", address.0, e),
+    }
+}
+
+fn accept_clients(
+    mut commands: Commands,
+    mut server: ResMut<Server>,
+    grid: Res<Grid>,
+    gen: Res<WorldGen>,
+    settings: Res<WorldSettings>,
+    tiles: Query<(Entity, &AsciiTile, Option<&Movement>, Has<PlayerMarker>, Option<&Appearance>, Option<&LightSource>)>,
+    mut add: EventWriter<AsciiAddEvent>,
+) {
+    loop {
+        let (stream, peer) = match server.listener.accept() {
+            Ok(accepted) => accepted,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+            Err(e) => {
+                error!("failed to accept client: {}", e);
+                break;
+            }
+        };
+        let mut connection = match Connection::new(stream) {
+            Ok(connection) => connection,
+            Err(e) => {
+                error!("failed to set up connection to {}: {}", peer, e);
+                continue;
+            }
+        };
+        let pos = spawn_point(&grid, &gen, server.clients.len() as i32, 0);
+        let player = spawn_player(&mut commands, pos, &mut add);
+        connection.send(&ServerMessage::Welcome {
+            player: player.to_bits(),
+            seed: gen.seed,
+            depth: settings.depth,
+        });
+        for (pos, terrain) in server.terrain.iter() {
+            connection.send(&ServerMessage::Terrain {
+                pos: pos.to_array(),
+                terrain: *terrain
+            });
+        }
+        for (entity, tile, movement, is_player, appearance, light) in tiles.iter() {
+            connection.send(&ServerMessage::Add {
+                entity: entity.to_bits(),
+                pos: tile.pos.to_array(),
+                player: is_player,
+                movement: movement.map(|m| m.v),
+                appearance: appearance.copied(),
+                light: light.copied(),
+            });
+        }
+        info!("client {} connected", peer);
+        server.clients.push(Client {
+            connection,
+            player
+        });
+    }
+}
+
+fn receive_commands(
+    mut commands: Commands,
+    mut server: ResMut<Server>,
+    mut pending: Query<&mut PendingCommand>,
+) {
+    let mut disconnected = Vec::new();
+    for client in server.clients.iter_mut() {
+        match client.connection.receive::<ClientMessage>() {
+            Ok(messages) => {
+                for message in messages {
+                    match message {
+                        ClientMessage::Command(cmd) => {
+                            if let Ok(mut pending) = pending.get_mut(client.player) {
+                                pending.0 = Some(cmd);
+                            }
+                        }
+                    }
+                }
+            }
+            Err(e) => {
+                info!("client disconnected: {}", e);
+                disconnected.push(client.player);
+            }
+        }
+    }
+    server.clients.retain(|client| !disconnected.contains(&client.player));
+    // Their removal reaches the other clients through the `AsciiRemoveEvent` it causes.
+    for player in disconnected {
+        commands.entity(player).despawn_recursive();
+    }
+}
+
+fn broadcast(
+    mut server: ResMut<Server>,
+    mut add: EventReader<AsciiAddEvent>,
+    mut mov: EventReader<AsciiMoveEvent>,
+    mut remove: EventReader<AsciiRemoveEvent>,
+    mut terrain: EventReader<TerrainChangedEvent>,
+    tiles: Query<(Option<&Movement>, Has<PlayerMarker>, Option<&Appearance>, Option<&LightSource>)>,
+) {
+    let mut messages = Vec::new();
+    for ev in add.read() {
+        let (movement, player, appearance, light) = tiles.get(ev.entity).unwrap_or((None, false, None, None));
+        messages.push(ServerMessage::Add {
+            entity: ev.entity.to_bits(),
+            pos: ev.pos.to_array(),
+            player,
+            movement: movement.map(|m| m.v),
+            appearance: appearance.copied(),
+            light: light.copied(),
+        });
+    }
+    for ev in mov.read() {
+        messages.push(ServerMessage::Move {
+            entity: ev.entity.to_bits(),
+            pos: ev.new_pos.to_array(),
+        });
+    }
+    for ev in remove.read() {
+        messages.push(ServerMessage::Remove {
+            entity: ev.entity.to_bits(),
+        });
+    }
+    for ev in terrain.read() {
+        server.terrain.insert(ev.pos, ev.new);
+        messages.push(ServerMessage::Terrain {
+            pos: ev.pos.to_array(),
+            terrain: ev.new,
+        });
+    }
+    for client in server.clients.iter_mut() {
+        for message in messages.iter() {
+            client.connection.send(message);
+        }
+        if let Err(e) = client.connection.flush() {
+            warn!("failed to send to client: {}", e);
+        }
+    }
+}
+
+fn connect(
+    mut commands: Commands,
+    mut connect: EventReader<ConnectEvent>,
+    address: Res<NetAddress>,
+    mut mode: ResMut<NetMode>,
+) {
+    if connect.read().last().is_none() {
+        return;
+    }
+    match TcpStream::connect(&address.0).and_then(Connection::new) {
+        Ok(connection) => {
+            info!("connected to {}", address.0);
+            commands.insert_resource(ServerConnection {
+                connection,
+                player: None,
+                entities: HashMap::new(),
+            });
+            *mode = NetMode::Client;
+        }
+        Err(e) => error!("failed to connect to {}: {}", address.0, e),
+    }
+}
+
+fn client_receive(
+    mut commands: Commands,
+    mut server: ResMut<ServerConnection>,
+    mut mode: ResMut<NetMode>,
+    mut settings: ResMut<WorldSettings>,
+    mut gen: ResMut<WorldGen>,
+    mut grid: ResMut<Grid>,
+    mut store: ResMut<ChunkStore>,
+    mut tiles: Query<(Entity, &mut AsciiTile)>,
+    mut add: EventWriter<AsciiAddEvent>,
+    mut mov: EventWriter<AsciiMoveEvent>,
+    mut changed: EventWriter<TerrainChangedEvent>,
+    mut loaded: EventWriter<WorldLoadedEvent>,
+    state: Res<State<MainState>>,
+    mut next_state: ResMut<NextState<MainState>>,
+) {
+    let messages = match server.connection.receive::<ServerMessage>() {
+        Ok(messages) => messages,
+        Err(e) => {
+            error!("lost connection to server: {}", e);
+            commands.remove_resource::<ServerConnection>();
+            *mode = NetMode::Offline;
+            return;
+        }
+    };
+    for message in messages {
+        match message {
+            ServerMessage::Welcome { player, seed, depth } => {
+                for (entity, _) in tiles.iter() {
+                    commands.entity(entity).despawn_recursive();
+                }
+                server.player = Some(player);
+                server.entities.clear();
+                gen.seed = seed;
+                settings.depth = depth;
+                *grid = Grid::new(settings.depth);
+                store.chunks.clear();
+                if *state.get() == MainState::InGame {
+                    loaded.send(WorldLoadedEvent);
+                } else {
+                    next_state.set(MainState::InGame);
+                }
+            }
+            ServerMessage::Add { entity: remote, pos, player, movement, appearance, light } => {
+                let pos = IVec3::from_array(pos);
+                let mut entity = commands.spawn(AsciiTile {pos});
+                if let Some(appearance) = appearance {
+                    entity.insert(appearance);
+                }
+                if let Some(light) = light {
+                    entity.insert(light);
+                }
+                if let Some(v) = movement {
+                    entity.insert(Movement {
+                        v,
+                        d: Vec3::ZERO
+                    });
+                }
+                if server.player == Some(remote) {
+                    entity.insert(PlayerMarker);
+                } else if player {
+                    entity.insert(RemotePlayer);
+                }
+                let entity = entity.id();
+                server.entities.insert(remote, entity);
+                grid.insert(entity, pos);
+                add.send(AsciiAddEvent {
+                    entity,
+                    pos
+                });
+            }
+            ServerMessage::Move { entity: remote, pos } => {
+                let Some(entity) = server.entities.get(&remote) else {
+                    continue;
+                };
+                if let Ok((entity, mut tile)) = tiles.get_mut(*entity) {
+                    let new_pos = IVec3::from_array(pos);
+                    mov.send(AsciiMoveEvent {
+                        entity,
+                        old_pos: tile.pos,
+                        new_pos
+                    });
+                    tile.pos = new_pos;
+                }
+            }
+            ServerMessage::Remove { entity: remote } => {
+                if let Some(entity) = server.entities.remove(&remote) {
+                    commands.entity(entity).despawn_recursive();
+                }
+            }
+            ServerMessage::Terrain { pos, terrain } => {
+                let pos = IVec3::from_array(pos);
+                // Reshaped chunks this client hasn't loaded are kept until it does.
+                let Some(old) = grid.terrain(pos) else {
+                    store.set_terrain(&gen, grid.depth(), pos, terrain);
+                    continue;
+                };
+                grid.set_terrain(pos, terrain);
+                changed.send(TerrainChangedEvent {
+                    pos,
+                    old,
+                    new: terrain
+                });
+            }
+        }
+    }
+}
+
+fn client_send(
+    mut server: ResMut<ServerConnection>,
+    mut commands: EventReader<PlayerCommand>,
+) {
+    for cmd in commands.read() {
+        server.connection.send(&ClientMessage::Command(*cmd));
+    }
+    if let Err(e) = server.connection.flush() {
+        warn!("failed to send to server: {}", e);
+    }
+}
+
+pub struct NetPlugin;
+impl Plugin for NetPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<NetMode>()
+            .init_resource::<NetAddress>()
+            .add_event::<ConnectEvent>()
+            .add_systems(Startup, start_server.run_if(resource_equals(NetMode::Server)))
+            .add_systems(Update, (
+                accept_clients,
+                receive_commands,
+                broadcast
+            ).chain().run_if(resource_exists::<Server>))
+            .add_systems(Update, connect)
+            .add_systems(Update, client_receive.before(ChunkSet).run_if(resource_exists::<ServerConnection>))
+            .add_systems(PostUpdate, client_send.run_if(resource_exists::<ServerConnection>));
+    }
+}
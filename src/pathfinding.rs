@@ -0,0 +1,309 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use crate::ascii_world::Grid;
+
+/// Nodes A* expands before giving up on an unreachable goal.
+const MAX_SEARCH: usize = 16384;
+/// Dijkstra maps kept in `PathCache` before it starts over.
+const MAX_CACHED: usize = 64;
+
+/// Whether a walker can stand at `pos` without falling. Entities are ignored,
+/// they move too often for a path to depend on them.
+pub fn walkable(grid: &Grid, pos: IVec3) -> bool {
+    grid.terrain(pos).is_some_and(|t| !t.is_solid()) && grid.supported(pos)
+}
+
+/// The cells a walker at `pos` can reach in one step and what each step costs,
+/// following the same climbing rules as `GridMover::try_climb`.
+pub fn edges(grid: &Grid, pos: IVec3) -> Vec<(IVec3, u32)> {
+    let mut edges = Vec::with_capacity(10);
+    for y in -1..=1 {
+        for x in -1..=1 {
+            let next = pos + IVec3::new(x, y, 0);
+            if (x, y) != (0, 0) && walkable(grid, next) {
+                edges.push(next);
+            }
+        }
+    }
+    let terrain = grid.terrain(pos).unwrap_or_default();
+    if terrain.climbs_up() && walkable(grid, pos + IVec3::Z) {
+        edges.push(pos + IVec3::Z);
+    }
+    let below = grid.terrain(pos - IVec3::Z).unwrap_or_default();
+    let can_descend = terrain.climbs_down() || below.climbs_up();
+    if can_descend && walkable(grid, pos - IVec3::Z) {
+        edges.push(pos - IVec3::Z);
+    }
+    edges.into_iter()
+        .map(|p| (p, grid.terrain(p).unwrap_or_default().move_cost()))
+        .collect()
+}
+
+/// Cells that reach `pos` in one step, the reverse of `edges`.
+fn sources_of(grid: &Grid, pos: IVec3) -> Vec<(IVec3, u32)> {
+    let cost = grid.terrain(pos).unwrap_or_default().move_cost();
+    grid.neighbours(pos)
+        .filter(|p| walkable(grid, *p))
+        .filter(|p| edges(grid, *p).iter().any(|(to, _)| *to == pos))
+        .map(|p| (p, cost))
+        .collect()
+}
+
+fn heuristic(a: IVec3, b: IVec3) -> u32 {
+    (a - b).abs().max_element() as u32
+}
+
+/// The cheapest walk from `start` to `goal`, excluding `start`.
+pub fn find_path(grid: &Grid, start: IVec3, goal: IVec3) -> Option<Vec<IVec3>> {
+    if start == goal {
+        return Some(Vec::new());
+    }
+    if !walkable(grid, goal) {
+        return None;
+    }
+    let mut open = BinaryHeap::new();
+    let mut came_from: HashMap<IVec3, IVec3> = HashMap::new();
+    let mut costs: HashMap<IVec3, u32> = HashMap::new();
+    costs.insert(start, 0);
+    open.push(Reverse((heuristic(start, goal), start.to_array())));
+    let mut expanded = 0;
+    while let Some(Reverse((_, pos))) = open.pop() {
+        let pos = IVec3::from_array(pos);
+        if pos == goal {
+            let mut path = vec![goal];
+            let mut at = goal;
+            while let Some(prev) = came_from.get(&at) {
+                if *prev == start {
+                    break;
+                }
+                path.push(*prev);
+                at = *prev;
+            }
+            path.reverse();
+            return Some(path);
+        }
+        expanded += 1;
+        if expanded > MAX_SEARCH {
+            return None;
+        }
+        let cost = costs[&pos];
+        for (next, step) in edges(grid, pos) {
+            let next_cost = cost + step;
+            if costs.get(&next).map_or(true, |c| next_cost < *c) {
+                costs.insert(next, next_cost);
+                came_from.insert(next, pos);
+                open.push(Reverse((next_cost + heuristic(next, goal), next.to_array())));
+            }
+        }
+    }
+    None
+}
+
+/// Cost of the cheapest walk from every cell to the nearest of a set of sources,
+/// up to `max_cost`. One map serves any number of walkers heading for the same sources.
+pub struct DijkstraMap {
+    costs: HashMap<IVec3, u32>,
+    max_cost: u32,
+}
+impl DijkstraMap {
+    pub fn new(grid: &Grid, sources: &[IVec3], max_cost: u32) -> Self {
+        let mut costs: HashMap<IVec3, u32> = HashMap::new();
+        let mut open = BinaryHeap::new();
+        for source in sources.iter().filter(|s| grid.contains(**s)) {
+            costs.insert(*source, 0);
+            open.push(Reverse((0, source.to_array())));
+        }
+        while let Some(Reverse((cost, pos))) = open.pop() {
+            let pos = IVec3::from_array(pos);
+            if costs.get(&pos).is_some_and(|c| *c < cost) {
+                continue;
+            }
+            for (prev, step) in sources_of(grid, pos) {
+                let prev_cost = cost + step;
+                if prev_cost <= max_cost && costs.get(&prev).map_or(true, |c| prev_cost < *c) {
+                    costs.insert(prev, prev_cost);
+                    open.push(Reverse((prev_cost, prev.to_array())));
+                }
+            }
+        }
+        Self {
+            costs,
+            max_cost
+        }
+    }
+    /// `None` for cells farther than `max_cost` from every source.
+    pub fn cost(&self, pos: IVec3) -> Option<u32> {
+        self.costs.get(&pos).copied()
+    }
+    /// The next step towards the nearest source.
+    pub fn downhill(&self, grid: &Grid, pos: IVec3) -> Option<IVec3> {
+        let here = self.cost(pos)?;
+        edges(grid, pos).into_iter()
+            .filter_map(|(p, _)| self.cost(p).map(|c| (p, c)))
+            .filter(|(_, c)| *c < here)
+            .min_by_key(|(_, c)| *c)
+            .map(|(p, _)| p)
+    }
+    /// The next step away from the sources, cells out of range counting as farthest.
+    pub fn uphill(&self, grid: &Grid, pos: IVec3) -> Option<IVec3> {
+        let here = self.cost(pos).unwrap_or(self.max_cost + 1);
+        edges(grid, pos).into_iter()
+            .map(|(p, _)| (p, self.cost(p).unwrap_or(self.max_cost + 1)))
+            .filter(|(_, c)| *c > here)
+            .max_by_key(|(_, c)| *c)
+            .map(|(p, _)| p)
+    }
+}
+
+/// Dijkstra maps towards entities, shared by everything heading for or away from the same
+/// one. A map is rebuilt once its entity has moved or the terrain within its reach changed,
+/// so at most once a tick however many creatures chase it.
+#[derive(Resource, Default)]
+pub struct PathCache {
+    maps: HashMap<(Entity, u32), CachedMap>,
+}
+struct CachedMap {
+    pos: IVec3,
+    revision: u64,
+    map: DijkstraMap,
+}
+impl PathCache {
+    pub fn dijkstra(&mut self, grid: &Grid, target: Entity, pos: IVec3, max_cost: u32) -> &DijkstraMap {
+        // Every step costs at least 1, nothing farther than `max_cost` from the target matters.
+        let reach = IVec3::splat(max_cost as i32);
+        let revision = grid.revision_in(pos - reach, pos + reach);
+        let key = (target, max_cost);
+        if self.maps.get(&key).is_some_and(|cached| cached.pos != pos || cached.revision != revision) {
+            self.maps.remove(&key);
+        }
+        if self.maps.len() >= MAX_CACHED {
+            self.clear();
+        }
+        &self.maps.entry(key)
+            .or_insert_with(|| CachedMap {
+                pos,
+                revision,
+                map: DijkstraMap::new(grid, &[pos], max_cost)
+            })
+            .map
+    }
+    pub fn clear(&mut self) {
+        self.maps.clear();
+    }
+}
+
+pub struct PathfindingPlugin;
+impl Plugin for PathfindingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PathCache>();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ascii_world::Terrain;
+    use crate::chunk::Chunk;
+
+    /// One empty chunk, everything on the bottom layer stands on bedrock.
+    fn floor() -> Grid {
+        let mut grid = Grid::new(2);
+        grid.insert_chunk(IVec3::ZERO, Chunk::default());
+        grid
+    }
+
+    fn wall(grid: &mut Grid, x: i32, ys: std::ops::RangeInclusive<i32>) {
+        for y in ys {
+            grid.set_terrain(IVec3::new(x, y, 0), Terrain::Stone);
+        }
+    }
+
+    #[test]
+    fn path_takes_diagonals_and_excludes_start() {
+        let grid = floor();
+        let path = find_path(&grid, IVec3::new(1, 1, 0), IVec3::new(5, 3, 0)).unwrap();
+        assert_eq!(path.len(), 4);
+        assert_eq!(path.last(), Some(&IVec3::new(5, 3, 0)));
+        assert!(!path.contains(&IVec3::new(1, 1, 0)));
+        assert_eq!(find_path(&grid, IVec3::new(1, 1, 0), IVec3::new(1, 1, 0)), Some(Vec::new()));
+    }
+
+    #[test]
+    fn path_goes_around_walls() {
+        let mut grid = floor();
+        wall(&mut grid, 5, 0..=8);
+        let start = IVec3::new(2, 2, 0);
+        let path = find_path(&grid, start, IVec3::new(8, 2, 0)).unwrap();
+        let mut at = start;
+        for step in &path {
+            assert!(walkable(&grid, *step));
+            assert_eq!((*step - at).abs().max_element(), 1);
+            at = *step;
+        }
+        assert!(path.iter().any(|p| p.y > 8));
+    }
+
+    #[test]
+    fn enclosed_goal_has_no_path() {
+        let mut grid = floor();
+        wall(&mut grid, 4, 0..=4);
+        for x in 0..4 {
+            grid.set_terrain(IVec3::new(x, 4, 0), Terrain::Stone);
+        }
+        assert_eq!(find_path(&grid, IVec3::new(10, 10, 0), IVec3::new(1, 1, 0)), None);
+        assert_eq!(find_path(&grid, IVec3::new(10, 10, 0), IVec3::new(4, 2, 0)), None);
+    }
+
+    #[test]
+    fn path_avoids_water_when_cheaper() {
+        let mut grid = floor();
+        for y in 0..=3 {
+            grid.set_terrain(IVec3::new(3, y, 0), Terrain::Water);
+        }
+        let path = find_path(&grid, IVec3::new(1, 1, 0), IVec3::new(5, 1, 0)).unwrap();
+        assert!(path.iter().all(|p| grid.terrain(*p) != Some(Terrain::Water)));
+    }
+
+    #[test]
+    fn dijkstra_costs_match_paths() {
+        let mut grid = floor();
+        wall(&mut grid, 5, 0..=8);
+        let source = IVec3::new(8, 2, 0);
+        let map = DijkstraMap::new(&grid, &[source], 100);
+        assert_eq!(map.cost(source), Some(0));
+        for start in [IVec3::new(2, 2, 0), IVec3::new(7, 7, 0), IVec3::new(3, 12, 0)] {
+            let path = find_path(&grid, start, source).unwrap();
+            assert_eq!(map.cost(start), Some(path.len() as u32));
+        }
+        assert_eq!(map.cost(IVec3::new(5, 2, 0)), None);
+    }
+
+    #[test]
+    fn dijkstra_stops_at_max_cost() {
+        let grid = floor();
+        let map = DijkstraMap::new(&grid, &[IVec3::new(10, 10, 0)], 3);
+        assert_eq!(map.cost(IVec3::new(13, 10, 0)), Some(3));
+        assert_eq!(map.cost(IVec3::new(14, 10, 0)), None);
+    }
+
+    #[test]
+    fn downhill_reaches_source_and_uphill_flees() {
+        let mut grid = floor();
+        wall(&mut grid, 5, 0..=8);
+        let source = IVec3::new(8, 2, 0);
+        let map = DijkstraMap::new(&grid, &[source], 100);
+        let mut at = IVec3::new(2, 2, 0);
+        for _ in 0..32 {
+            if at == source {
+                break;
+            }
+            at = map.downhill(&grid, at).unwrap();
+        }
+        assert_eq!(at, source);
+        assert_eq!(map.downhill(&grid, source), None);
+        let away = map.uphill(&grid, source).unwrap();
+        assert!(map.cost(away).unwrap() > 0);
+    }
+}
@@ -1,82 +1,291 @@
+use std::time::Duration;
 use bevy::prelude::*;
-use crate::ascii_world::{AsciiAddEvent, AsciiMoveEvent, AsciiTile, WorldSettings};
-use crate::living_entity::Movement;
+use serde::{Deserialize, Serialize};
+use crate::ascii_world::{Appearance, AsciiAddEvent, AsciiTile, BlockReason, Blocking, Gravity, Grid, GridMover};
+use crate::combat::{pick_target, Attack, AttackEvent, FireEvent, RangedAttack};
+use crate::config::Config;
+use crate::fov::Viewshed;
+use crate::input::{Action, ActionState, InputMapSet};
+use crate::item::{Inventory, ItemAction, ItemActionEvent};
+use crate::lighting::LightSource;
+use crate::living_entity::{Faction, Health, Movement};
+use crate::net::NetMode;
+use crate::terraform::{BuildSelection, TerrainAction, TerrainActionEvent};
+use crate::turn::{Actor, NORMAL_SPEED};
+use crate::world_map::{spawn_point, WorldGen, WorldGenSet};
 
 #[derive(Component)]
 pub struct PlayerMarker;
 
+pub const PLAYER_HEALTH: i32 = 20;
+pub const PLAYER_CARRY_WEIGHT: f32 = 30.;
+pub const PLAYER_CARRY_VOLUME: f32 = 40.;
+pub const PLAYER_ATTACK: Attack = Attack {
+    damage: 3,
+    accuracy: 0.8
+};
+/// The sling every player carries.
+pub const PLAYER_RANGED: RangedAttack = RangedAttack {
+    damage: 2,
+    range: 8
+};
+/// The torch every player carries.
+pub const PLAYER_LIGHT: LightSource = LightSource {
+    radius: 8,
+    color: Color::rgb(1., 0.8, 0.5),
+    intensity: 1.
+};
 
-fn startup(
-    mut commands: Commands,
-    mut event: EventWriter<AsciiAddEvent>,
-) {
+/// Players are drawn above anything else sharing their cell.
+pub const PLAYER_APPEARANCE: Appearance = Appearance {
+    glyph: '@',
+    fg: Color::PINK,
+    bg: Color::NONE,
+    priority: 100
+};
+
+/// The column a player came into the world at and gets back up at after dying.
+#[derive(Component, Debug, Clone, Copy, Default)]
+pub struct SpawnPoint(pub IVec2);
+
+/// The command a player will carry out on their next turn.
+#[derive(Component, Default)]
+pub struct PendingCommand(pub Option<PlayerCommand>);
+
+/// A request for the player to move or handle items, applied locally or sent to the server when connected.
+#[derive(Event, Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct PlayerCommand {
+    pub dx: i32,
+    pub dy: i32,
+    #[serde(default)]
+    pub dz: i32,
+    #[serde(default)]
+    pub item: Option<ItemAction>,
+    /// Shoots at the nearest target in range.
+    #[serde(default)]
+    pub fire: bool,
+    /// Digs or builds in the direction of the move instead of moving.
+    #[serde(default)]
+    pub terrain: Option<TerrainAction>
+}
+
+pub fn spawn_player(
+    commands: &mut Commands,
+    pos: IVec3,
+    event: &mut EventWriter<AsciiAddEvent>,
+) -> Entity {
     let entity = commands.spawn((
-        AsciiTile {pos: UVec3::new(30, 30, 2) },
+        AsciiTile {pos},
         Movement {
             v: 20.,
             d: Vec3::ZERO
         },
         PlayerMarker,
+        PLAYER_APPEARANCE,
+        Health::new(PLAYER_HEALTH),
+        Faction::Player,
+        PLAYER_ATTACK,
+        PLAYER_RANGED,
+        PLAYER_LIGHT,
+        Actor::new(NORMAL_SPEED),
+        PendingCommand::default(),
+        Inventory::new(PLAYER_CARRY_WEIGHT, PLAYER_CARRY_VOLUME),
+        SpawnPoint(pos.truncate()),
+        Blocking,
+        Gravity,
     )).id();
     event.send(AsciiAddEvent {
         entity,
-        pos: UVec3::new(30, 30, 2)
+        pos
     });
+    entity
 }
 
+fn startup(
+    mut commands: Commands,
+    mut event: EventWriter<AsciiAddEvent>,
+    grid: Res<Grid>,
+    gen: Res<WorldGen>,
+) {
+    spawn_player(&mut commands, spawn_point(&grid, &gen, 0, 0), &mut event);
+}
+
+/// Turns movement keys into commands, one step when a key goes down and then `Movement.v`
+/// a second while it is held. Repeats are counted on the fixed clock that drives the real
+/// time scheduler, so they come at the same rate whatever the frame rate.
+/// How often the player actually gets to move is up to the turn scheduler.
+/// Holding dig or build turns the moves into digging or building that way.
 fn keyboard_input(
-    key: Res<ButtonInput<KeyCode>>,
-    time: Res<Time>,
-    mut player: Query<(Entity, &mut Movement, &mut AsciiTile), With<PlayerMarker>>,
-    mut mov: EventWriter<AsciiMoveEvent>,
-    settings: Res<WorldSettings>
+    actions: Res<ActionState>,
+    fixed: Res<Time<Fixed>>,
+    mut last: Local<Duration>,
+    selection: Res<BuildSelection>,
+    mut player: Query<&mut Movement, With<PlayerMarker>>,
+    mut command: EventWriter<PlayerCommand>,
 ) {
-    if let Ok((entity ,mut movement, mut tile))= player.get_single_mut() {
-        let dx =  time.delta_seconds() * movement.v;
-        if key.pressed(KeyCode::KeyW) {
-            movement.d.y -= dx;
+    // Creating a world restarts the fixed clock.
+    let elapsed = fixed.elapsed().saturating_sub(*last);
+    *last = fixed.elapsed();
+    if let Ok(mut movement) = player.get_single_mut() {
+        let dx = elapsed.as_secs_f32() * movement.v;
+        for (action, axis, sign) in [
+            (Action::MoveNorth, 1, -1.),
+            (Action::MoveWest, 0, -1.),
+            (Action::MoveSouth, 1, 1.),
+            (Action::MoveEast, 0, 1.),
+        ] {
+            if actions.just_pressed(action) {
+                movement.d[axis] = sign;
+            } else if actions.pressed(action) {
+                movement.d[axis] += sign * dx;
+            }
         }
-        if key.pressed(KeyCode::KeyA) {
-            movement.d.x -= dx;
+        let mut cmd = PlayerCommand::default();
+        if actions.just_pressed(Action::PickUp) {
+            cmd.item = Some(ItemAction::PickUp);
         }
-        if key.pressed(KeyCode::KeyS) {
-            movement.d.y += dx;
+        cmd.fire = actions.just_pressed(Action::Fire);
+        if actions.just_pressed(Action::MoveUp) {
+            cmd.dz = 1;
+        } else if actions.just_pressed(Action::MoveDown) {
+            cmd.dz = -1;
         }
-        if key.pressed(KeyCode::KeyD) {
-            movement.d.x += dx;
+        // What is left over counts towards the next repeat.
+        if movement.d.x.abs() >= 1. {
+            cmd.dx = movement.d.x as i32;
+            movement.d.x -= cmd.dx as f32;
         }
-        let mut new_pos = tile.pos.clone();
-        if movement.d.x >= 1. {
-            if new_pos.x < settings.size.x - 1 {
-                new_pos.x += movement.d.x as u32;
-            }
-            movement.d.x = 0.;
+        if movement.d.y.abs() >= 1. {
+            cmd.dy = movement.d.y as i32;
+            movement.d.y -= cmd.dy as f32;
         }
-        if movement.d.x <= -1.{
-            if 0 < new_pos.x {
-                new_pos.x -= -movement.d.x as u32;
+        if cmd.dx != 0 || cmd.dy != 0 || cmd.dz != 0 {
+            if actions.pressed(Action::Dig) {
+                cmd.terrain = Some(TerrainAction::Dig);
+            } else if actions.pressed(Action::Build) {
+                cmd.terrain = Some(TerrainAction::Build(selection.0));
             }
-            movement.d.x = 0.;
         }
-        if movement.d.y >= 1. {
-            if new_pos.y < settings.size.y - 1 {
-                new_pos.y += movement.d.y as u32;
-            }
-            movement.d.y = 0.;
+        if cmd.dx != 0 || cmd.dy != 0 || cmd.dz != 0 || cmd.item.is_some() || cmd.fire {
+            command.send(cmd);
         }
-        if movement.d.y <= -1. {
-            if 0 < new_pos.y {
-                new_pos.y -= -movement.d.y as u32;
-            }
-            movement.d.y = 0.;
+    }
+}
+
+fn apply_movement_speed(
+    config: Option<Res<Config>>,
+    mut player: Query<(&mut Movement, Ref<PlayerMarker>)>,
+) {
+    let Some(config) = config else {
+        return;
+    };
+    for (mut movement, marker) in player.iter_mut() {
+        if config.is_changed() || marker.is_added() {
+            movement.v = config.movement_speed;
         }
-        if tile.pos != new_pos {
-            mov.send(AsciiMoveEvent {
+    }
+}
+
+impl PlayerCommand {
+    /// At most one cell per axis, so a move can never skip over a wall.
+    pub fn delta(&self) -> IVec3 {
+        IVec3::new(self.dx.signum(), self.dy.signum(), 0)
+    }
+    /// The neighbouring cell the command digs or builds in, including up and down.
+    pub fn target(&self, pos: IVec3) -> IVec3 {
+        pos + IVec3::new(self.dx.signum(), self.dy.signum(), self.dz.signum())
+    }
+    /// Climbs when the command has a vertical part, walks otherwise.
+    pub fn apply(&self, entity: Entity, mover: &mut GridMover) -> Result<IVec3, BlockReason> {
+        if self.dz != 0 {
+            mover.try_climb(entity, self.dz > 0)
+        } else {
+            mover.try_move(entity, self.delta())
+        }
+    }
+}
+
+/// Queues the local player's commands until their turn comes up. A newer command replaces
+/// one still waiting, like the server does for its clients.
+fn queue_commands(
+    mut commands: EventReader<PlayerCommand>,
+    mut player: Query<&mut PendingCommand, With<PlayerMarker>>,
+) {
+    if let (Some(cmd), Ok(mut pending)) = (commands.read().last(), player.get_single_mut()) {
+        pending.0 = Some(*cmd);
+    }
+}
+
+/// Carries out the queued command of every player whose turn it is. Walking into walls
+/// or shooting with nothing to shoot at costs no energy, so it never wastes a turn.
+/// Walking into something the player attacks hits it instead.
+pub(crate) fn apply_commands(
+    mut players: Query<(Entity, &mut PendingCommand, &mut Actor, &Faction, Option<&RangedAttack>, Option<&Viewshed>)>,
+    targets: Query<(Entity, &Faction), With<Health>>,
+    mut mover: GridMover,
+    mut item_actions: EventWriter<ItemActionEvent>,
+    mut attacks: EventWriter<AttackEvent>,
+    mut fire: EventWriter<FireEvent>,
+    mut terrain_actions: EventWriter<TerrainActionEvent>,
+) {
+    for (entity, mut pending, mut actor, faction, ranged, viewshed) in players.iter_mut() {
+        if !actor.ready() {
+            continue;
+        }
+        let Some(cmd) = pending.0.take() else {
+            continue;
+        };
+        if let Some(action) = cmd.item {
+            item_actions.send(ItemActionEvent {
                 entity,
-                old_pos: tile.pos.clone(),
-                new_pos
+                action
             });
-            tile.pos = new_pos;
+            actor.spend();
+            continue;
+        }
+        if cmd.fire {
+            let Some(pos) = mover.grid().position_of(entity) else {
+                continue;
+            };
+            let target = ranged.and_then(|ranged| pick_target(
+                mover.grid(),
+                pos,
+                *faction,
+                ranged.range,
+                viewshed,
+                targets.iter().filter_map(|(other, faction)| Some((mover.grid().position_of(other)?, *faction)))
+            ));
+            if let Some(target) = target {
+                fire.send(FireEvent {
+                    shooter: entity,
+                    target
+                });
+                actor.spend();
+            }
+            continue;
+        }
+        if let Some(action) = cmd.terrain {
+            let target = mover.grid().position_of(entity).map(|pos| cmd.target(pos));
+            if let Some(target) = target.filter(|target| mover.grid().contains(*target)) {
+                terrain_actions.send(TerrainActionEvent {
+                    entity,
+                    action,
+                    target
+                });
+                actor.spend();
+            }
+            continue;
+        }
+        match cmd.apply(entity, &mut mover) {
+            Ok(_) | Err(BlockReason::ClosedDoor(_)) => actor.spend(),
+            Err(BlockReason::Entity(target)) if targets.get(target).is_ok_and(|(_, other)| faction.attacks(*other)) => {
+                attacks.send(AttackEvent {
+                    attacker: entity,
+                    target
+                });
+                actor.spend();
+            }
+            Err(_) => {}
         }
     }
 }
@@ -85,7 +294,12 @@ pub struct PlayerPlugin;
 impl Plugin for PlayerPlugin {
     fn build(&self, app: &mut App) {
         app
-            .add_systems(Startup, startup)
-            .add_systems(PreUpdate, keyboard_input);
+            .add_event::<PlayerCommand>()
+            .add_systems(Startup, startup.after(WorldGenSet))
+            .add_systems(PreUpdate, (
+                apply_movement_speed,
+                keyboard_input.run_if(resource_exists::<ActionState>),
+                queue_commands.run_if(not(resource_equals(NetMode::Client)))
+            ).chain().after(InputMapSet));
     }
-}
\ No newline at end of file
+}
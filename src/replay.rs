@@ -0,0 +1,338 @@
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use bevy::app::AppExit;
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use bevy::time::{TimeSystem, TimeUpdateStrategy};
+use serde::{Deserialize, Serialize};
+use crate::ascii_world::{Appearance, AsciiTile, Grid, Terrain};
+use crate::chunk::ChunkStore;
+use crate::living_entity::Health;
+use crate::net::NetMode;
+use crate::player::PlayerCommand;
+use crate::save::LoadEvent;
+use crate::turn::{TickCount, TimeMode};
+use crate::world_map::{NewWorldEvent, WorldCreatedEvent};
+
+pub const REPLAY_VERSION: u32 = 1;
+const REPLAY_DIR: &str = "replays";
+const REPLAY_EXTENSION: &str = "ron";
+
+/// A session from the moment its world was created: the world's settings, then for every
+/// frame how long it took and the commands the player gave in it. Frame times decide when
+/// ticks run, the commands and the seeded `GameRng` what happens in them.
+#[derive(Serialize, Deserialize)]
+pub struct ReplayFile {
+    pub version: u32,
+    pub name: String,
+    pub seed: u32,
+    pub depth: u32,
+    pub time_mode: TimeMode,
+    pub frames: Vec<ReplayFrame>,
+    /// `WorldHasher::hash` after the last frame. Only known for sessions that ended with the
+    /// app, a new or loaded world has already replaced the recorded one by the time it is noticed.
+    #[serde(default)]
+    pub hash: Option<u64>,
+}
+#[derive(Serialize, Deserialize)]
+pub struct ReplayFrame {
+    /// Real time the frame took, in nanoseconds.
+    pub delta: u64,
+    #[serde(default)]
+    pub commands: Vec<PlayerCommand>,
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(std::io::Error),
+    Serialize(ron::Error),
+    Deserialize(ron::error::SpannedError),
+    Version(u32),
+}
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayError::Io(e) => write!(f, "{}", e),
+            ReplayError::Serialize(e) => write!(f, "{}", e),
+            ReplayError::Deserialize(e) => write!(f, "{}", e),
+            ReplayError::Version(v) => write!(f, "unsupported replay version {} (expected {})", v, REPLAY_VERSION),
+        }
+    }
+}
+impl From<std::io::Error> for ReplayError {
+    fn from(e: std::io::Error) -> Self {
+        ReplayError::Io(e)
+    }
+}
+
+pub fn replay_path(name: &str) -> PathBuf {
+    Path::new(REPLAY_DIR).join(name).with_extension(REPLAY_EXTENSION)
+}
+pub fn write_replay(path: &Path, replay: &ReplayFile) -> Result<(), ReplayError> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let text = ron::ser::to_string_pretty(replay, ron::ser::PrettyConfig::default().compact_arrays(true))
+        .map_err(ReplayError::Serialize)?;
+    fs::write(path, text)?;
+    Ok(())
+}
+pub fn read_replay(path: &Path) -> Result<ReplayFile, ReplayError> {
+    let text = fs::read_to_string(path)?;
+    let replay: ReplayFile = ron::from_str(&text).map_err(ReplayError::Deserialize)?;
+    if replay.version != REPLAY_VERSION {
+        return Err(ReplayError::Version(replay.version));
+    }
+    Ok(replay)
+}
+
+/// FNV-1a. Unlike the std hashers its output is specified, so hashes written by one build
+/// can be checked by another. Everything goes in as fixed width little endian integers,
+/// `Hash` impls write native endian and `usize` lengths.
+struct Fnv(u64);
+impl Fnv {
+    fn new() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+    fn bytes(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 = (self.0 ^ *b as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+    fn u32(&mut self, n: u32) {
+        self.bytes(&n.to_le_bytes());
+    }
+    fn i32(&mut self, n: i32) {
+        self.bytes(&n.to_le_bytes());
+    }
+    fn u64(&mut self, n: u64) {
+        self.bytes(&n.to_le_bytes());
+    }
+    fn len(&mut self, len: usize) {
+        self.u64(len as u64);
+    }
+    fn pos(&mut self, pos: IVec3) {
+        for n in pos.to_array() {
+            self.i32(n);
+        }
+    }
+    fn runs(&mut self, runs: &[(Terrain, u32)]) {
+        self.len(runs.len());
+        for (terrain, n) in runs {
+            self.bytes(&[*terrain as u8]);
+            self.u32(*n);
+        }
+    }
+}
+
+/// Sums up the state of the world in one number: every chunk visited, what stands where
+/// and how healthy it is, and the tick count. Entity ids don't go into it, they needn't
+/// match between runs.
+#[derive(SystemParam)]
+pub struct WorldHasher<'w, 's> {
+    grid: Res<'w, Grid>,
+    store: Res<'w, ChunkStore>,
+    ticks: Res<'w, TickCount>,
+    tiles: Query<'w, 's, (&'static AsciiTile, Option<&'static Appearance>, Option<&'static Health>)>,
+}
+impl<'w, 's> WorldHasher<'w, 's> {
+    pub fn hash(&self) -> u64 {
+        let mut hasher = Fnv::new();
+        hasher.u64(self.ticks.0);
+        let mut loaded = self.grid.chunks().collect::<Vec<_>>();
+        loaded.sort_by_key(|(pos, _)| pos.to_array());
+        hasher.len(loaded.len());
+        for (pos, chunk) in loaded {
+            hasher.pos(pos);
+            hasher.runs(&chunk.encode());
+        }
+        let mut stored = self.store.chunks.iter().collect::<Vec<_>>();
+        stored.sort_by_key(|(pos, _)| pos.to_array());
+        hasher.len(stored.len());
+        for (pos, chunk) in stored {
+            hasher.pos(*pos);
+            match &chunk.terrain {
+                Some(terrain) => {
+                    hasher.bytes(&[1]);
+                    hasher.runs(&terrain.encode());
+                }
+                None => hasher.bytes(&[0]),
+            }
+            hasher.len(chunk.tiles.len());
+            for tile in chunk.tiles.iter() {
+                hasher.pos(IVec3::from_array(tile.pos));
+            }
+        }
+        let mut tiles = self.tiles.iter()
+            .map(|(tile, appearance, health)| (tile.pos.to_array(), appearance.map(|a| a.glyph), health.map(|h| h.current)))
+            .collect::<Vec<_>>();
+        tiles.sort();
+        hasher.len(tiles.len());
+        for (pos, glyph, health) in tiles {
+            hasher.pos(IVec3::from_array(pos));
+            match glyph {
+                Some(glyph) => {
+                    hasher.bytes(&[1]);
+                    hasher.u32(glyph as u32);
+                }
+                None => hasher.bytes(&[0]),
+            }
+            match health {
+                Some(health) => {
+                    hasher.bytes(&[1]);
+                    hasher.i32(health);
+                }
+                None => hasher.bytes(&[0]),
+            }
+        }
+        hasher.0
+    }
+}
+
+/// Present while sessions are recorded. Every world created offline is recorded into
+/// `replays/`, named after the world, until the next one is created or loaded or the app exits.
+#[derive(Resource, Default)]
+pub struct Recorder {
+    replay: Option<ReplayFile>,
+}
+
+fn finish_recording(replay: ReplayFile) {
+    let path = replay_path(&replay.name);
+    match write_replay(&path, &replay) {
+        Ok(()) => info!("recorded {} frames to {}", replay.frames.len(), path.display()),
+        Err(e) => error!("failed to write replay to {}: {}", path.display(), e),
+    }
+}
+
+fn record(
+    mut recorder: ResMut<Recorder>,
+    mode: Res<NetMode>,
+    time: Res<Time<Real>>,
+    hasher: WorldHasher,
+    mut commands: EventReader<PlayerCommand>,
+    mut created: EventReader<WorldCreatedEvent>,
+    mut load: EventReader<LoadEvent>,
+    mut exit: EventReader<AppExit>,
+) {
+    let commands = commands.read().copied().collect::<Vec<_>>();
+    if let Some(replay) = recorder.replay.as_mut() {
+        replay.frames.push(ReplayFrame {
+            delta: time.delta().as_nanos() as u64,
+            commands,
+        });
+    }
+    let created = created.read().last().map(|ev| &ev.0);
+    let loaded = load.read().last().is_some();
+    if exit.read().last().is_some() {
+        if let Some(mut replay) = recorder.replay.take() {
+            replay.hash = Some(hasher.hash());
+            finish_recording(replay);
+        }
+        return;
+    }
+    if created.is_some() || loaded {
+        if let Some(replay) = recorder.replay.take() {
+            finish_recording(replay);
+        }
+    }
+    if let Some(ev) = created.filter(|_| *mode == NetMode::Offline) {
+        recorder.replay = Some(ReplayFile {
+            version: REPLAY_VERSION,
+            name: ev.name.clone(),
+            seed: ev.seed,
+            depth: ev.depth,
+            time_mode: ev.time_mode,
+            frames: Vec::new(),
+            hash: None,
+        });
+    }
+}
+
+/// Present while a replay plays. Creates the recorded world, then feeds it the recorded
+/// frame times and commands, checks the final hash and exits.
+#[derive(Resource)]
+pub struct Replayer {
+    replay: ReplayFile,
+    next: usize,
+    started: bool,
+}
+impl Replayer {
+    pub fn new(replay: ReplayFile) -> Self {
+        Self {
+            replay,
+            next: 0,
+            started: false
+        }
+    }
+}
+
+fn start_replay(
+    replayer: Res<Replayer>,
+    mut new_world: EventWriter<NewWorldEvent>,
+) {
+    let replay = &replayer.replay;
+    info!("replaying {} frames of {}", replay.frames.len(), replay.name);
+    new_world.send(NewWorldEvent {
+        name: replay.name.clone(),
+        seed: replay.seed,
+        depth: replay.depth,
+        time_mode: replay.time_mode,
+    });
+}
+
+/// Runs before time advances, so the frame takes as long as the recorded one did.
+fn feed_replay(
+    mut replayer: ResMut<Replayer>,
+    mut strategy: ResMut<TimeUpdateStrategy>,
+    mut commands: EventWriter<PlayerCommand>,
+) {
+    if !replayer.started {
+        return;
+    }
+    let Some(frame) = replayer.replay.frames.get(replayer.next) else {
+        return;
+    };
+    *strategy = TimeUpdateStrategy::ManualDuration(Duration::from_nanos(frame.delta));
+    commands.send_batch(frame.commands.iter().copied());
+    replayer.next += 1;
+}
+
+/// Compares against the recorded hash once every frame has played and exits, a mismatch
+/// is logged as an error.
+fn finish_replay(
+    mut replayer: ResMut<Replayer>,
+    mut created: EventReader<WorldCreatedEvent>,
+    hasher: WorldHasher,
+    mut exit: EventWriter<AppExit>,
+) {
+    if !replayer.started {
+        // Recordings start on the frame after their world was created.
+        replayer.started = created.read().last().is_some();
+        return;
+    }
+    if replayer.next < replayer.replay.frames.len() {
+        return;
+    }
+    let hash = hasher.hash();
+    match replayer.replay.hash {
+        Some(expected) if expected != hash => {
+            error!("replay diverged: world hash {:016x}, recorded {:016x}", hash, expected);
+        }
+        Some(_) => info!("replay matched the recorded world hash {:016x}", hash),
+        None => info!("replay finished with world hash {:016x}, none was recorded", hash),
+    }
+    exit.send(AppExit);
+}
+
+pub struct ReplayPlugin;
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_systems(Last, record.run_if(resource_exists::<Recorder>))
+            .add_systems(Startup, start_replay.run_if(resource_exists::<Replayer>))
+            .add_systems(First, feed_replay.before(TimeSystem).run_if(resource_exists::<Replayer>))
+            .add_systems(Last, finish_replay.run_if(resource_exists::<Replayer>));
+    }
+}
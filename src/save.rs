@@ -0,0 +1,478 @@
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use crate::ascii_world::{Appearance, AsciiAddEvent, AsciiTile, Blocking, Gravity, Grid, Terrain, UpdateViewLayerEvent, ViewLayer, WorldSettings};
+use crate::chunk::{Chunk, ChunkSet, ChunkStore, StoredChunk};
+use crate::combat::Projectile;
+use crate::fov::{Memory, Viewshed};
+use crate::input::{Action, ActionState};
+use crate::item::{spawn_held_item, Inventory, Item};
+use crate::lighting::LightSource;
+use crate::living_entity::{Archetype, Creature, Faction, Health, Movement};
+use crate::MainState;
+use crate::player::{PendingCommand, PlayerMarker, SpawnPoint, PLAYER_ATTACK, PLAYER_RANGED};
+use crate::turn::{Actor, TimeMode, NORMAL_SPEED};
+use crate::world_map::WorldGen;
+
+pub const SAVE_VERSION: u32 = 2;
+const SAVE_DIR: &str = "saves";
+const SAVE_EXTENSION: &str = "ron";
+
+/// Name of the save file the current world is written to.
+#[derive(Resource)]
+pub struct SaveSlot(pub String);
+impl FromWorld for SaveSlot {
+    fn from_world(_world: &mut World) -> Self {
+        Self(String::from("world"))
+    }
+}
+
+#[derive(Event)]
+pub struct SaveEvent;
+#[derive(Event)]
+pub struct LoadEvent(pub PathBuf);
+/// Sent after a save has been applied while already in game, so views can rebuild.
+#[derive(Event)]
+pub struct WorldLoadedEvent;
+
+#[derive(Serialize, Deserialize)]
+pub struct SaveFile {
+    pub version: u32,
+    pub name: String,
+    pub seed: u32,
+    pub depth: u32,
+    pub view_layer: u32,
+    #[serde(default)]
+    pub time_mode: TimeMode,
+    /// Every chunk visited so far, loaded or not. The rest has never been generated.
+    pub chunks: Vec<SavedChunk>,
+    /// What stands in the loaded chunks.
+    pub tiles: Vec<SavedTile>,
+}
+#[derive(Serialize, Deserialize)]
+pub struct SavedChunk {
+    pub pos: [i32; 3],
+    /// Run-length encoded terrain, see `Chunk::encode`, only for chunks changed since they were generated.
+    #[serde(default)]
+    pub terrain: Option<Vec<(Terrain, u32)>>,
+    /// What stands in the chunk while it is unloaded.
+    #[serde(default)]
+    pub tiles: Vec<SavedTile>,
+}
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SavedTile {
+    pub pos: [i32; 3],
+    pub player: bool,
+    pub movement: Option<f32>,
+    #[serde(default)]
+    pub blocking: bool,
+    #[serde(default)]
+    pub gravity: bool,
+    #[serde(default)]
+    pub appearance: Option<Appearance>,
+    #[serde(default)]
+    pub health: Option<Health>,
+    #[serde(default)]
+    pub faction: Option<Faction>,
+    #[serde(default)]
+    pub creature: Option<Archetype>,
+    #[serde(default)]
+    pub light: Option<LightSource>,
+    #[serde(default)]
+    pub item: Option<Item>,
+    #[serde(default)]
+    pub inventory: Option<SavedInventory>,
+    /// Cells a player has explored, see `fov::Memory`.
+    #[serde(default)]
+    pub memory: Option<Vec<[i32; 3]>>,
+    /// The column a player respawns at, see `player::SpawnPoint`.
+    #[serde(default)]
+    pub spawn: Option<[i32; 2]>,
+}
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SavedInventory {
+    pub max_weight: f32,
+    pub max_volume: f32,
+    pub items: Vec<Item>,
+}
+
+#[derive(Debug)]
+pub enum SaveError {
+    Io(std::io::Error),
+    Serialize(ron::Error),
+    Deserialize(ron::error::SpannedError),
+    Version(u32),
+    Terrain([i32; 3]),
+}
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveError::Io(e) => write!(f, "{}", e),
+            SaveError::Serialize(e) => write!(f, "{}", e),
+            SaveError::Deserialize(e) => write!(f, "{}", e),
+            SaveError::Version(v) => write!(f, "unsupported save version {} (expected {})", v, SAVE_VERSION),
+            SaveError::Terrain(pos) => write!(f, "terrain of chunk {:?} does not fill a chunk", pos),
+        }
+    }
+}
+impl From<std::io::Error> for SaveError {
+    fn from(e: std::io::Error) -> Self {
+        SaveError::Io(e)
+    }
+}
+
+pub fn slot_path(name: &str) -> PathBuf {
+    Path::new(SAVE_DIR).join(name).with_extension(SAVE_EXTENSION)
+}
+
+/// Every save in the save directory, most recently written first.
+pub fn list_saves() -> Vec<PathBuf> {
+    let Ok(dir) = fs::read_dir(SAVE_DIR) else {
+        return Vec::new();
+    };
+    let mut saves = dir
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == SAVE_EXTENSION))
+        .filter_map(|path| {
+            let modified = fs::metadata(&path).and_then(|m| m.modified()).ok()?;
+            Some((modified, path))
+        })
+        .collect::<Vec<_>>();
+    saves.sort_by(|a, b| b.0.cmp(&a.0));
+    saves.into_iter().map(|(_, path)| path).collect()
+}
+pub fn most_recent_save() -> Option<PathBuf> {
+    list_saves().into_iter().next()
+}
+
+pub fn write_save(path: &Path, save: &SaveFile) -> Result<(), SaveError> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let text = ron::ser::to_string_pretty(save, ron::ser::PrettyConfig::default())
+        .map_err(SaveError::Serialize)?;
+    fs::write(path, text)?;
+    Ok(())
+}
+pub fn read_save(path: &Path) -> Result<SaveFile, SaveError> {
+    let text = fs::read_to_string(path)?;
+    let save: SaveFile = ron::from_str(&text).map_err(SaveError::Deserialize)?;
+    if save.version != SAVE_VERSION {
+        return Err(SaveError::Version(save.version));
+    }
+    for chunk in save.chunks.iter() {
+        if chunk.terrain.as_ref().is_some_and(|runs| Chunk::decode(runs).is_none()) {
+            return Err(SaveError::Terrain(chunk.pos));
+        }
+    }
+    Ok(save)
+}
+
+/// Turns `AsciiTile` entities into `SavedTile`s, for saves and for chunks that unload.
+/// Held items are saved with their holder's inventory, projectiles aren't saved at all.
+#[derive(SystemParam)]
+pub struct TileSaver<'w, 's> {
+    tiles: Query<'w, 's, (&'static AsciiTile, Option<&'static Movement>, Has<PlayerMarker>, Has<Blocking>, Has<Gravity>, Option<&'static Appearance>, Option<&'static Health>, Option<&'static Faction>, Option<&'static Creature>, Option<&'static LightSource>, Option<&'static Item>, Option<&'static Inventory>, Option<&'static Memory>, Option<&'static SpawnPoint>), Without<Projectile>>,
+    held: Query<'w, 's, &'static Item>,
+}
+impl<'w, 's> TileSaver<'w, 's> {
+    pub fn save(&self, entity: Entity) -> Option<SavedTile> {
+        let (tile, movement, player, blocking, gravity, appearance, health, faction, creature, light, item, inventory, memory, spawn) = self.tiles.get(entity).ok()?;
+        Some(SavedTile {
+            pos: tile.pos.to_array(),
+            player,
+            movement: movement.map(|m| m.v),
+            blocking,
+            gravity,
+            appearance: appearance.copied(),
+            health: health.copied(),
+            faction: faction.copied(),
+            creature: creature.map(|c| c.archetype),
+            light: light.copied(),
+            item: item.copied(),
+            inventory: inventory.map(|inventory| SavedInventory {
+                max_weight: inventory.max_weight,
+                max_volume: inventory.max_volume,
+                items: inventory.items.iter().filter_map(|e| self.held.get(*e).ok()).copied().collect(),
+            }),
+            memory: memory.map(|memory| memory.cells.iter().map(|pos| pos.to_array()).collect()),
+            spawn: spawn.map(|spawn| spawn.0.to_array()),
+        })
+    }
+}
+
+/// Spawns a saved entity back into the world, with everything it needs to act again.
+pub fn spawn_saved_tile(
+    commands: &mut Commands,
+    saved: SavedTile,
+    add: &mut EventWriter<AsciiAddEvent>,
+) -> Entity {
+    let pos = IVec3::from_array(saved.pos);
+    let mut entity = commands.spawn(AsciiTile {pos});
+    if let Some(v) = saved.movement {
+        entity.insert(Movement {
+            v,
+            d: Vec3::ZERO
+        });
+    }
+    if saved.player {
+        let spawn = SpawnPoint(saved.spawn.map(IVec2::from_array).unwrap_or_default());
+        entity.insert((PlayerMarker, Actor::new(NORMAL_SPEED), PendingCommand::default(), PLAYER_ATTACK, PLAYER_RANGED, spawn));
+    }
+    if saved.blocking {
+        entity.insert(Blocking);
+    }
+    if saved.gravity {
+        entity.insert(Gravity);
+    }
+    if let Some(appearance) = saved.appearance {
+        entity.insert(appearance);
+    }
+    if let Some(health) = saved.health {
+        entity.insert(health);
+    }
+    if let Some(faction) = saved.faction {
+        entity.insert(faction);
+    }
+    if let Some(cells) = saved.memory {
+        entity.insert(Memory {
+            cells: cells.into_iter().map(IVec3::from_array).collect()
+        });
+    }
+    if let Some(light) = saved.light {
+        entity.insert(light);
+    }
+    if let Some(item) = saved.item {
+        entity.insert((item, item.kind.stats().appearance));
+    }
+    if let Some(archetype) = saved.creature {
+        let stats = archetype.stats();
+        entity.insert((Creature::new(archetype), Viewshed::new(stats.sight), Actor::new(stats.speed), stats.attack));
+        if let Some(ranged) = stats.ranged {
+            entity.insert(ranged);
+        }
+    }
+    let entity = entity.id();
+    if let Some(saved) = saved.inventory {
+        let mut inventory = Inventory::new(saved.max_weight, saved.max_volume);
+        for item in saved.items {
+            inventory.items.push(spawn_held_item(commands, entity, item));
+        }
+        commands.entity(entity).insert(inventory);
+    }
+    add.send(AsciiAddEvent {
+        entity,
+        pos
+    });
+    entity
+}
+
+fn keyboard_input(
+    actions: Res<ActionState>,
+    mut save: EventWriter<SaveEvent>,
+) {
+    if actions.just_pressed(Action::QuickSave) {
+        save.send(SaveEvent);
+    }
+}
+
+fn save_world(
+    mut save: EventReader<SaveEvent>,
+    slot: Res<SaveSlot>,
+    settings: Res<WorldSettings>,
+    gen: Res<WorldGen>,
+    grid: Res<Grid>,
+    store: Res<ChunkStore>,
+    saver: TileSaver,
+    tiles: Query<Entity, (With<AsciiTile>, Without<Projectile>)>,
+    view: Query<&ViewLayer>,
+) {
+    if save.read().last().is_none() {
+        return;
+    }
+    let loaded = grid.chunks().map(|(pos, chunk)| SavedChunk {
+        pos: pos.to_array(),
+        terrain: chunk.modified.then(|| chunk.encode()),
+        tiles: Vec::new(),
+    });
+    let stored = store.chunks.iter().map(|(pos, stored)| SavedChunk {
+        pos: pos.to_array(),
+        terrain: stored.terrain.as_ref().map(|chunk| chunk.encode()),
+        tiles: stored.tiles.clone(),
+    });
+    let file = SaveFile {
+        version: SAVE_VERSION,
+        name: slot.0.clone(),
+        seed: gen.seed,
+        depth: settings.depth,
+        view_layer: view.get_single().map(|v| v.0).unwrap_or(0),
+        time_mode: settings.time_mode,
+        chunks: loaded.chain(stored).collect(),
+        tiles: tiles.iter().filter_map(|entity| saver.save(entity)).collect(),
+    };
+    let path = slot_path(&slot.0);
+    match write_save(&path, &file) {
+        Ok(()) => info!("saved world to {}", path.display()),
+        Err(e) => error!("failed to save world to {}: {}", path.display(), e),
+    }
+}
+
+fn load_world(
+    mut commands: Commands,
+    mut load: EventReader<LoadEvent>,
+    mut slot: ResMut<SaveSlot>,
+    mut settings: ResMut<WorldSettings>,
+    mut gen: ResMut<WorldGen>,
+    mut grid: ResMut<Grid>,
+    mut store: ResMut<ChunkStore>,
+    tiles: Query<Entity, With<AsciiTile>>,
+    mut view: Query<&mut ViewLayer>,
+    mut add: EventWriter<AsciiAddEvent>,
+    mut update_view_layer: EventWriter<UpdateViewLayerEvent>,
+    mut loaded: EventWriter<WorldLoadedEvent>,
+    state: Res<State<MainState>>,
+    mut next_state: ResMut<NextState<MainState>>,
+) {
+    let Some(LoadEvent(path)) = load.read().last() else {
+        return;
+    };
+    let file = match read_save(path) {
+        Ok(file) => file,
+        Err(e) => {
+            error!("failed to load world from {}: {}", path.display(), e);
+            return;
+        }
+    };
+
+    for entity in tiles.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    slot.0 = file.name;
+    gen.seed = file.seed;
+    settings.depth = file.depth;
+    settings.time_mode = file.time_mode;
+    *grid = Grid::new(settings.depth);
+    // Every chunk starts out stored, they stream back in around the players.
+    store.chunks = file.chunks.into_iter().map(|saved| (IVec3::from_array(saved.pos), StoredChunk {
+        terrain: saved.terrain.and_then(|runs| Chunk::decode(&runs)),
+        tiles: saved.tiles,
+    })).collect();
+    for saved in file.tiles {
+        let pos = IVec3::from_array(saved.pos);
+        let entity = spawn_saved_tile(&mut commands, saved, &mut add);
+        grid.insert(entity, pos);
+    }
+    for mut view in view.iter_mut() {
+        view.0 = file.view_layer.min(settings.depth - 1);
+        update_view_layer.send(UpdateViewLayerEvent(view.0));
+    }
+    info!("loaded world from {}", path.display());
+
+    if *state.get() == MainState::InGame {
+        loaded.send(WorldLoadedEvent);
+    } else {
+        next_state.set(MainState::InGame);
+    }
+}
+
+pub struct SavePlugin;
+impl Plugin for SavePlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<SaveSlot>()
+            .add_event::<SaveEvent>()
+            .add_event::<LoadEvent>()
+            .add_event::<WorldLoadedEvent>()
+            .add_systems(Update, keyboard_input.run_if(in_state(MainState::InGame)).run_if(resource_exists::<ActionState>))
+            .add_systems(Update, (save_world, load_world).chain().before(ChunkSet));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A file in the temp directory, removed again when dropped.
+    struct Scratch(PathBuf);
+    impl Scratch {
+        fn new(name: &str) -> Self {
+            Self(std::env::temp_dir().join(format!("{}_{}.{}", name, std::process::id(), SAVE_EXTENSION)))
+        }
+    }
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn save_file(version: u32, chunks: Vec<SavedChunk>) -> SaveFile {
+        SaveFile {
+            version,
+            name: String::from("test"),
+            seed: 7,
+            depth: 16,
+            view_layer: 3,
+            time_mode: TimeMode::default(),
+            chunks,
+            tiles: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn current_version_round_trips() {
+        let path = Scratch::new("save_round_trip");
+        let chunk = SavedChunk {
+            pos: [-1, 0, 0],
+            terrain: Some(Chunk::default().encode()),
+            tiles: Vec::new(),
+        };
+        write_save(&path.0, &save_file(SAVE_VERSION, vec![chunk])).unwrap();
+        let save = read_save(&path.0).unwrap();
+        assert_eq!(save.version, SAVE_VERSION);
+        assert_eq!((save.seed, save.depth, save.view_layer), (7, 16, 3));
+        assert_eq!(save.chunks[0].pos, [-1, 0, 0]);
+    }
+
+    #[test]
+    fn other_versions_are_rejected() {
+        let path = Scratch::new("save_old_version");
+        for version in [SAVE_VERSION - 1, SAVE_VERSION + 1] {
+            write_save(&path.0, &save_file(version, Vec::new())).unwrap();
+            assert!(matches!(read_save(&path.0), Err(SaveError::Version(v)) if v == version));
+        }
+    }
+
+    #[test]
+    fn terrain_must_fill_its_chunk() {
+        let path = Scratch::new("save_short_terrain");
+        let chunk = SavedChunk {
+            pos: [0, 0, 0],
+            terrain: Some(vec![(Terrain::Stone, 10)]),
+            tiles: Vec::new(),
+        };
+        write_save(&path.0, &save_file(SAVE_VERSION, vec![chunk])).unwrap();
+        assert!(matches!(read_save(&path.0), Err(SaveError::Terrain([0, 0, 0]))));
+    }
+
+    #[test]
+    fn fields_added_later_default_when_missing() {
+        let path = Scratch::new("save_missing_fields");
+        let text = format!(
+            "(version: {}, name: \"old\", seed: 1, depth: 16, view_layer: 0, chunks: [(pos: (0, 0, 0))], \
+            tiles: [(pos: (1, 2, 3), player: true, movement: Some(20.0))])",
+            SAVE_VERSION
+        );
+        fs::write(&path.0, text).unwrap();
+        let save = read_save(&path.0).unwrap();
+        assert_eq!(save.time_mode, TimeMode::default());
+        assert!(save.chunks[0].terrain.is_none() && save.chunks[0].tiles.is_empty());
+        let tile = &save.tiles[0];
+        assert_eq!(tile.pos, [1, 2, 3]);
+        assert!(tile.player && !tile.blocking && !tile.gravity);
+        assert!(tile.inventory.is_none() && tile.memory.is_none() && tile.spawn.is_none());
+    }
+}
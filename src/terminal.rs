@@ -0,0 +1,455 @@
+use std::io::{self, BufWriter, Stdout, Write};
+use std::time::Duration;
+use bevy::app::AppExit;
+use bevy::input::keyboard::{Key, KeyboardInput, NativeKey};
+use bevy::input::{ButtonState, InputSystem};
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use crossterm::event::{self, Event, KeyCode as TermKey, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags, PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags};
+use crossterm::style::{Color as TermColor, Print, ResetColor, SetBackgroundColor, SetForegroundColor};
+use crossterm::{cursor, queue, terminal};
+use rand::Rng;
+use crate::ascii_render::CellView;
+use crate::ascii_world::{AsciiTile, AsciiWorldSet, ViewLayer, WorldSettings};
+use crate::fov::FovSet;
+use crate::input::{Action, ActionState};
+use crate::lighting::LightingSet;
+use crate::living_entity::Health;
+use crate::MainState;
+use crate::net::ConnectEvent;
+use crate::player::PlayerMarker;
+use crate::rng::GameRng;
+use crate::save::{most_recent_save, LoadEvent};
+use crate::turn::TimeMode;
+use crate::ui::BANNER;
+use crate::world_map::NewWorldEvent;
+
+/// How long a key counts as held after the terminal reported it, for terminals that don't
+/// report releases. Long enough for one step at the default movement speed, short enough
+/// for no second one.
+const KEY_HOLD: f32 = 0.075;
+const MENU_ITEMS: [&str; 4] = ["Continue", "Connect", "New", "Exit"];
+const LETTERS: [KeyCode; 26] = [
+    KeyCode::KeyA, KeyCode::KeyB, KeyCode::KeyC, KeyCode::KeyD, KeyCode::KeyE, KeyCode::KeyF,
+    KeyCode::KeyG, KeyCode::KeyH, KeyCode::KeyI, KeyCode::KeyJ, KeyCode::KeyK, KeyCode::KeyL,
+    KeyCode::KeyM, KeyCode::KeyN, KeyCode::KeyO, KeyCode::KeyP, KeyCode::KeyQ, KeyCode::KeyR,
+    KeyCode::KeyS, KeyCode::KeyT, KeyCode::KeyU, KeyCode::KeyV, KeyCode::KeyW, KeyCode::KeyX,
+    KeyCode::KeyY, KeyCode::KeyZ,
+];
+const DIGITS: [KeyCode; 10] = [
+    KeyCode::Digit0, KeyCode::Digit1, KeyCode::Digit2, KeyCode::Digit3, KeyCode::Digit4,
+    KeyCode::Digit5, KeyCode::Digit6, KeyCode::Digit7, KeyCode::Digit8, KeyCode::Digit9,
+];
+const FUNCTION_KEYS: [KeyCode; 12] = [
+    KeyCode::F1, KeyCode::F2, KeyCode::F3, KeyCode::F4, KeyCode::F5, KeyCode::F6,
+    KeyCode::F7, KeyCode::F8, KeyCode::F9, KeyCode::F10, KeyCode::F11, KeyCode::F12,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Cell {
+    glyph: char,
+    fg: [u8; 3],
+    bg: [u8; 3],
+}
+const BLANK: Cell = Cell {
+    glyph: ' ',
+    fg: [255, 255, 255],
+    bg: [0, 0, 0]
+};
+
+fn rgb(color: Color) -> [u8; 3] {
+    [color.r(), color.g(), color.b()].map(|c| (c.clamp(0., 1.) * 255.) as u8)
+}
+
+/// The terminal in raw mode on the alternate screen, given back as it was when dropped,
+/// which is when the app is, exit or panic.
+#[derive(Resource)]
+struct Terminal {
+    out: BufWriter<Stdout>,
+    size: UVec2,
+    /// What is on screen, only cells differing from `frame` are sent.
+    shown: Vec<Cell>,
+    frame: Vec<Cell>,
+    /// Whether the terminal reports key releases, otherwise keys are let go after `KEY_HOLD`.
+    releases: bool,
+    held: HashMap<KeyCode, f32>,
+}
+impl Terminal {
+    fn new() -> io::Result<Self> {
+        let mut out = BufWriter::new(io::stdout());
+        terminal::enable_raw_mode()?;
+        queue!(out, terminal::EnterAlternateScreen, cursor::Hide)?;
+        let releases = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if releases {
+            queue!(out, PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES))?;
+        }
+        out.flush()?;
+        let (width, height) = terminal::size()?;
+        let mut term = Self {
+            out,
+            size: UVec2::ZERO,
+            shown: Vec::new(),
+            frame: Vec::new(),
+            releases,
+            held: HashMap::new(),
+        };
+        term.resize(UVec2::new(width as u32, height as u32));
+        Ok(term)
+    }
+    fn resize(&mut self, size: UVec2) {
+        let cells = (size.x * size.y) as usize;
+        self.size = size;
+        self.frame = vec![BLANK; cells];
+        // Nothing matches, so the next frame is sent whole.
+        self.shown = vec![Cell { glyph: '\0', ..BLANK }; cells];
+    }
+    fn clear(&mut self) {
+        self.frame.fill(BLANK);
+    }
+    fn put(&mut self, x: u32, y: u32, cell: Cell) {
+        if x < self.size.x && y < self.size.y {
+            self.frame[(y * self.size.x + x) as usize] = cell;
+        }
+    }
+    fn text(&mut self, x: u32, y: u32, text: &str, fg: Color, bg: Color) {
+        for (i, glyph) in text.chars().enumerate() {
+            self.put(x + i as u32, y, Cell { glyph, fg: rgb(fg), bg: rgb(bg) });
+        }
+    }
+    /// Sends the cells that changed since the last frame.
+    fn present(&mut self) -> io::Result<()> {
+        let mut cursor_at = None;
+        let mut colors = None;
+        for i in 0..self.frame.len() {
+            let cell = self.frame[i];
+            if self.shown[i] == cell {
+                continue;
+            }
+            let (x, y) = (i as u32 % self.size.x, i as u32 / self.size.x);
+            if cursor_at != Some(i) {
+                queue!(self.out, cursor::MoveTo(x as u16, y as u16))?;
+            }
+            if colors != Some((cell.fg, cell.bg)) {
+                let [r, g, b] = cell.fg;
+                queue!(self.out, SetForegroundColor(TermColor::Rgb { r, g, b }))?;
+                let [r, g, b] = cell.bg;
+                queue!(self.out, SetBackgroundColor(TermColor::Rgb { r, g, b }))?;
+                colors = Some((cell.fg, cell.bg));
+            }
+            queue!(self.out, Print(cell.glyph))?;
+            // Terminals disagree on where the cursor goes past the end of a row.
+            cursor_at = (x + 1 < self.size.x).then_some(i + 1);
+            self.shown[i] = cell;
+        }
+        self.out.flush()
+    }
+}
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        if self.releases {
+            let _ = queue!(self.out, PopKeyboardEnhancementFlags);
+        }
+        let _ = queue!(self.out, ResetColor, cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = self.out.flush();
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// The main menu as drawn in the terminal, the banner glitching like the windowed one.
+#[derive(Resource)]
+struct TerminalMenu {
+    selected: usize,
+    banner: Vec<Vec<Cell>>,
+    timer: Timer,
+}
+impl Default for TerminalMenu {
+    fn default() -> Self {
+        Self {
+            selected: 0,
+            banner: BANNER.iter().map(|row| row.chars().map(|glyph| Cell { glyph, ..BLANK }).collect()).collect(),
+            timer: Timer::new(Duration::from_millis(50), TimerMode::Repeating),
+        }
+    }
+}
+
+/// The key bevy would report for a terminal key, symbols by the key they are typed with
+/// on a US layout so `<` and `>` climb like `,` and `.` do.
+fn key_code(key: TermKey) -> Option<KeyCode> {
+    Some(match key {
+        TermKey::Char(c) if c.is_ascii_alphabetic() => LETTERS[(c.to_ascii_lowercase() as u8 - b'a') as usize],
+        TermKey::Char(c) if c.is_ascii_digit() => DIGITS[(c as u8 - b'0') as usize],
+        TermKey::Char(' ') => KeyCode::Space,
+        TermKey::Char(',' | '<') => KeyCode::Comma,
+        TermKey::Char('.' | '>') => KeyCode::Period,
+        TermKey::Char('-' | '_') => KeyCode::Minus,
+        TermKey::Char('=' | '+') => KeyCode::Equal,
+        TermKey::Char('/' | '?') => KeyCode::Slash,
+        TermKey::F(n @ 1..=12) => FUNCTION_KEYS[n as usize - 1],
+        TermKey::Enter => KeyCode::Enter,
+        TermKey::Esc => KeyCode::Escape,
+        TermKey::Tab | TermKey::BackTab => KeyCode::Tab,
+        TermKey::Backspace => KeyCode::Backspace,
+        TermKey::Left => KeyCode::ArrowLeft,
+        TermKey::Right => KeyCode::ArrowRight,
+        TermKey::Up => KeyCode::ArrowUp,
+        TermKey::Down => KeyCode::ArrowDown,
+        _ => return None,
+    })
+}
+
+fn keyboard_event(key_code: KeyCode, state: ButtonState) -> KeyboardInput {
+    KeyboardInput {
+        key_code,
+        logical_key: Key::Unidentified(NativeKey::Unidentified),
+        state,
+        window: Entity::PLACEHOLDER,
+    }
+}
+
+fn startup(mut commands: Commands, mut exit: EventWriter<AppExit>) {
+    match Terminal::new() {
+        Ok(term) => {
+            commands.insert_resource(term);
+            commands.spawn(ViewLayer(0));
+        }
+        Err(e) => {
+            error!("failed to set up the terminal: {e}");
+            exit.send(AppExit);
+        }
+    }
+}
+
+/// Turns what the terminal read from stdin into keyboard events, so the bindings and
+/// everything reading `ActionState` work as they do in a window. Ctrl-C quits.
+fn read_keys(
+    mut term: ResMut<Terminal>,
+    time: Res<Time>,
+    mut keys: EventWriter<KeyboardInput>,
+    mut exit: EventWriter<AppExit>,
+) {
+    let term = &mut *term;
+    term.held.retain(|key, left| {
+        *left -= time.delta_seconds();
+        if *left <= 0. {
+            keys.send(keyboard_event(*key, ButtonState::Released));
+        }
+        *left > 0.
+    });
+    while event::poll(Duration::ZERO).unwrap_or(false) {
+        match event::read() {
+            Ok(Event::Key(key)) => {
+                if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == TermKey::Char('c') {
+                    exit.send(AppExit);
+                    continue;
+                }
+                let Some(key_code) = key_code(key.code) else {
+                    continue;
+                };
+                if key.kind == KeyEventKind::Release {
+                    keys.send(keyboard_event(key_code, ButtonState::Released));
+                    continue;
+                }
+                keys.send(keyboard_event(key_code, ButtonState::Pressed));
+                if !term.releases {
+                    term.held.insert(key_code, KEY_HOLD);
+                }
+            }
+            Ok(Event::Resize(width, height)) => term.resize(UVec2::new(width as u32, height as u32)),
+            Ok(_) => {}
+            Err(e) => {
+                error!("failed to read from the terminal: {}", e);
+                break;
+            }
+        }
+    }
+}
+
+fn clear_frame(mut term: ResMut<Terminal>) {
+    term.clear();
+}
+
+fn menu_input(
+    actions: Res<ActionState>,
+    settings: Res<WorldSettings>,
+    mut rng: ResMut<GameRng>,
+    mut menu: ResMut<TerminalMenu>,
+    mut load: EventWriter<LoadEvent>,
+    mut connect: EventWriter<ConnectEvent>,
+    mut new_world: EventWriter<NewWorldEvent>,
+    mut exit: EventWriter<AppExit>,
+) {
+    let len = MENU_ITEMS.len();
+    if actions.just_pressed(Action::MenuNext) {
+        menu.selected = (menu.selected + 1) % len;
+    }
+    if actions.just_pressed(Action::MenuPrevious) {
+        menu.selected = (menu.selected + len - 1) % len;
+    }
+    if !actions.just_pressed(Action::MenuConfirm) {
+        return;
+    }
+    match MENU_ITEMS[menu.selected] {
+        "Continue" => {
+            if let Some(path) = most_recent_save() {
+                load.send(LoadEvent(path));
+            }
+        }
+        "Connect" => {
+            connect.send(ConnectEvent);
+        }
+        "New" => {
+            new_world.send(NewWorldEvent {
+                name: String::from("world"),
+                seed: rng.gen(),
+                depth: settings.depth,
+                time_mode: TimeMode::default(),
+            });
+        }
+        _ => {
+            exit.send(AppExit);
+        }
+    }
+}
+
+fn draw_menu(
+    mut term: ResMut<Terminal>,
+    mut menu: ResMut<TerminalMenu>,
+    mut rng: ResMut<GameRng>,
+    time: Res<Time>,
+) {
+    menu.timer.tick(time.delta());
+    if menu.timer.just_finished() {
+        let glyphs = "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789!@#$%^&*()_+-=[]{}|;':\",./<>?`~".chars().collect::<Vec<_>>();
+        let colors = [Color::RED, Color::GREEN, Color::BLUE, Color::YELLOW, Color::PURPLE, Color::ORANGE];
+        let y = rng.gen_range(0..menu.banner.len());
+        let x = rng.gen_range(0..menu.banner[y].len());
+        let cell = &mut menu.banner[y][x];
+        if cell.glyph != ' ' {
+            if rng.gen_bool(0.5) {
+                *cell = Cell { glyph: glyphs[rng.gen_range(0..glyphs.len())], ..BLANK };
+            } else {
+                cell.fg = rgb(colors[rng.gen_range(0..colors.len())]);
+            }
+        }
+    }
+    let width = menu.banner[0].len() as u32;
+    let left = term.size.x.saturating_sub(width) / 2;
+    let top = term.size.y.saturating_sub(menu.banner.len() as u32 + 2) / 2;
+    for (y, row) in menu.banner.iter().enumerate() {
+        for (x, cell) in row.iter().enumerate() {
+            term.put(left + x as u32, top + y as u32, *cell);
+        }
+    }
+    let mut x = left + 5;
+    let y = top + menu.banner.len() as u32 + 1;
+    for (i, item) in MENU_ITEMS.iter().enumerate() {
+        if i == menu.selected {
+            term.text(x, y, item, Color::BLUE, Color::WHITE);
+        } else {
+            term.text(x, y, item, Color::WHITE, Color::BLACK);
+        }
+        x += item.len() as u32 + 5;
+    }
+}
+
+/// Keeps the view on the player's layer, like the windowed renderer does when following.
+fn follow_player(
+    player: Query<&AsciiTile, With<PlayerMarker>>,
+    mut view: Query<&mut ViewLayer>,
+) {
+    if let Ok(tile) = player.get_single() {
+        for mut view in view.iter_mut() {
+            view.0 = tile.pos.z as u32;
+        }
+    }
+}
+
+/// What the column under `cell` looks like from layer `top`: the first glyph and the first
+/// background on the way down, lower layers fading out the way `update_visibility` fades them.
+/// Cells never seen are see-through, as in the shader.
+fn column(view: &CellView, cell: IVec2, top: i32) -> Cell {
+    let mut glyph = None;
+    for z in (0..=top).rev() {
+        let (tile, fg, bg) = view.tile(cell.extend(z));
+        let fog = fg.a();
+        if fog < 0.25 {
+            continue;
+        }
+        let alpha = (z + 5) as f32 / (top + 5) as f32;
+        let shade = |color: Color| {
+            let mut rgb = [color.r(), color.g(), color.b()];
+            if fog < 0.75 {
+                // Remembered cells are drawn dim and washed out.
+                let grey = rgb[0] * 0.3 + rgb[1] * 0.59 + rgb[2] * 0.11;
+                rgb = rgb.map(|c| (c + (grey - c) * 0.6) * 0.4);
+            }
+            Color::rgb(rgb[0] * alpha, rgb[1] * alpha, rgb[2] * alpha)
+        };
+        let c = char::from_u32(tile).unwrap_or('?');
+        if glyph.is_none() && c != ' ' {
+            glyph = Some((c, rgb(shade(fg))));
+        }
+        if bg.a() > 0. {
+            let (glyph, fg) = glyph.unwrap_or((' ', BLANK.fg));
+            return Cell { glyph, fg, bg: rgb(shade(bg)) };
+        }
+    }
+    glyph.map_or(BLANK, |(glyph, fg)| Cell { glyph, fg, ..BLANK })
+}
+
+/// Draws the view layer around the player, with a status line along the bottom.
+fn draw_world(
+    mut term: ResMut<Terminal>,
+    view: CellView,
+    layer: Query<&ViewLayer>,
+    player: Query<(&AsciiTile, Option<&Health>), With<PlayerMarker>>,
+) {
+    let Ok(layer) = layer.get_single() else {
+        return;
+    };
+    let player = player.get_single().ok();
+    let centre = player.map_or(IVec2::ZERO, |(tile, _)| tile.pos.truncate());
+    let size = term.size.saturating_sub(UVec2::new(0, 1));
+    let origin = centre - size.as_ivec2() / 2;
+    for y in 0..size.y {
+        for x in 0..size.x {
+            let cell = column(&view, origin + IVec2::new(x as i32, y as i32), layer.0 as i32);
+            term.put(x, y, cell);
+        }
+    }
+    let health = player.and_then(|(_, health)| health)
+        .map(|health| format!("HP {}/{}   ", health.current, health.max))
+        .unwrap_or_default();
+    let status = format!(" {}Layer {}   F5 save   Ctrl-C quit", health, layer.0);
+    term.text(0, size.y, &status, Color::WHITE, Color::BLACK);
+}
+
+fn present(mut term: ResMut<Terminal>) {
+    if let Err(e) = term.present() {
+        error!("failed to draw to the terminal: {}", e);
+    }
+}
+
+/// Draws the world and the main menu to the terminal with truecolor escape codes and reads
+/// the keyboard from stdin instead, for playing without a window, e.g. over SSH. Needs
+/// bevy's `InputPlugin` for the keyboard events to end up in `ButtonInput<KeyCode>`.
+pub struct TerminalPlugin;
+impl Plugin for TerminalPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<TerminalMenu>()
+            .add_systems(Startup, startup)
+            // Without a terminal, the app exits after its first frame.
+            .add_systems(PreUpdate, read_keys.before(InputSystem).run_if(resource_exists::<Terminal>))
+            .add_systems(Update, clear_frame.run_if(resource_exists::<Terminal>))
+            .add_systems(Update, (menu_input, draw_menu).chain().after(clear_frame)
+                .run_if(in_state(MainState::MainMenu))
+                .run_if(resource_exists::<Terminal>))
+            .add_systems(Update, (follow_player, draw_world).chain()
+                .after(clear_frame)
+                .after(AsciiWorldSet).after(FovSet).after(LightingSet)
+                .run_if(in_state(MainState::InGame))
+                .run_if(resource_exists::<Terminal>))
+            .add_systems(Last, present.run_if(resource_exists::<Terminal>));
+    }
+}
@@ -0,0 +1,190 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use crate::ascii_world::{AsciiAddEvent, Grid, Terrain, TerrainChangedEvent};
+use crate::input::{Action, ActionState};
+use crate::item::{spawn_item, Inventory, Item, ItemKind};
+use crate::player::apply_commands;
+use crate::turn::{Tick, TickSet};
+
+/// What can be built on an empty cell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Structure {
+    #[default]
+    Wall,
+    Floor,
+    Ramp,
+    Stairs,
+}
+impl Structure {
+    pub const ALL: [Structure; 4] = [Structure::Wall, Structure::Floor, Structure::Ramp, Structure::Stairs];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Structure::Wall => "wall",
+            Structure::Floor => "floor",
+            Structure::Ramp => "ramp",
+            Structure::Stairs => "stairs",
+        }
+    }
+    pub fn terrain(&self) -> Terrain {
+        match self {
+            Structure::Wall => Terrain::Wall,
+            Structure::Floor => Terrain::Floor,
+            Structure::Ramp => Terrain::Ramp,
+            Structure::Stairs => Terrain::StairsUp,
+        }
+    }
+    /// Items used up by building it.
+    pub fn materials(&self) -> Item {
+        match self {
+            Structure::Wall => Item::new(ItemKind::Rock, 2),
+            Structure::Floor => Item::new(ItemKind::Stick, 2),
+            Structure::Ramp => Item::new(ItemKind::Rock, 3),
+            Structure::Stairs => Item::new(ItemKind::Stick, 3),
+        }
+    }
+}
+
+/// What digging out `terrain` leaves behind.
+fn dig_yield(terrain: Terrain) -> Option<Item> {
+    match terrain {
+        Terrain::Stone | Terrain::Wall => Some(Item::new(ItemKind::Rock, 1)),
+        Terrain::Floor => Some(Item::new(ItemKind::Stick, 1)),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TerrainAction {
+    /// Turns a solid cell into open air. Digging the cell below opens it up to the layer beneath.
+    Dig,
+    Build(Structure),
+}
+
+#[derive(Event)]
+pub struct TerrainActionEvent {
+    pub entity: Entity,
+    pub action: TerrainAction,
+    pub target: IVec3
+}
+
+/// What came of a `TerrainActionEvent`.
+#[derive(Event, Debug, Clone, Copy)]
+pub enum TerraformEvent {
+    Dug { entity: Entity, pos: IVec3, terrain: Terrain },
+    Built { entity: Entity, pos: IVec3, structure: Structure },
+    CantDig { entity: Entity, pos: IVec3 },
+    CantBuild { entity: Entity, pos: IVec3 },
+    MissingMaterials { entity: Entity, structure: Structure },
+}
+
+/// The structure the local player builds next.
+#[derive(Resource, Default)]
+pub struct BuildSelection(pub Structure);
+
+fn cycle_structure(
+    actions: Res<ActionState>,
+    mut selection: ResMut<BuildSelection>,
+) {
+    if actions.just_pressed(Action::CycleStructure) {
+        let i = Structure::ALL.iter().position(|s| *s == selection.0).unwrap_or(0);
+        selection.0 = Structure::ALL[(i + 1) % Structure::ALL.len()];
+        info!("building {}", selection.0.name());
+    }
+}
+
+/// Takes `needed` out of the inventory if it holds enough of it.
+fn consume(
+    commands: &mut Commands,
+    inventory: &mut Inventory,
+    items: &mut Query<&mut Item>,
+    needed: Item,
+) -> bool {
+    let held = inventory.items.iter()
+        .filter_map(|e| items.get(*e).ok())
+        .filter(|item| item.kind == needed.kind)
+        .map(|item| item.count)
+        .sum::<u32>();
+    if held < needed.count {
+        return false;
+    }
+    let mut remaining = needed.count;
+    inventory.items.retain(|e| {
+        let Ok(mut item) = items.get_mut(*e) else {
+            return true;
+        };
+        if remaining == 0 || item.kind != needed.kind {
+            return true;
+        }
+        let n = remaining.min(item.count);
+        item.count -= n;
+        remaining -= n;
+        if item.count == 0 {
+            commands.entity(*e).despawn_recursive();
+            return false;
+        }
+        true
+    });
+    true
+}
+
+fn handle_terrain_actions(
+    mut commands: Commands,
+    mut actions: EventReader<TerrainActionEvent>,
+    mut grid: ResMut<Grid>,
+    mut holders: Query<&mut Inventory>,
+    mut items: Query<&mut Item>,
+    mut add: EventWriter<AsciiAddEvent>,
+    mut changed: EventWriter<TerrainChangedEvent>,
+    mut events: EventWriter<TerraformEvent>,
+) {
+    for ev in actions.read() {
+        let (entity, pos) = (ev.entity, ev.target);
+        let Some(old) = grid.terrain(pos) else {
+            continue;
+        };
+        let new = match ev.action {
+            TerrainAction::Dig => {
+                if !old.is_solid() || old == Terrain::Bedrock {
+                    events.send(TerraformEvent::CantDig { entity, pos });
+                    continue;
+                }
+                grid.set_terrain(pos, Terrain::Air);
+                if let Some(item) = dig_yield(old) {
+                    spawn_item(&mut commands, item, pos, &mut add);
+                }
+                events.send(TerraformEvent::Dug { entity, pos, terrain: old });
+                Terrain::Air
+            }
+            TerrainAction::Build(structure) => {
+                if old != Terrain::Air || grid.is_occupied(pos) {
+                    events.send(TerraformEvent::CantBuild { entity, pos });
+                    continue;
+                }
+                let Ok(mut inventory) = holders.get_mut(entity) else {
+                    continue;
+                };
+                if !consume(&mut commands, &mut inventory, &mut items, structure.materials()) {
+                    events.send(TerraformEvent::MissingMaterials { entity, structure });
+                    continue;
+                }
+                grid.set_terrain(pos, structure.terrain());
+                events.send(TerraformEvent::Built { entity, pos, structure });
+                structure.terrain()
+            }
+        };
+        changed.send(TerrainChangedEvent { pos, old, new });
+    }
+}
+
+pub struct TerraformPlugin;
+impl Plugin for TerraformPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<BuildSelection>()
+            .add_event::<TerrainActionEvent>()
+            .add_event::<TerraformEvent>()
+            .add_systems(Update, cycle_structure.run_if(resource_exists::<ActionState>))
+            .add_systems(Tick, handle_terrain_actions.in_set(TickSet::Players).after(apply_commands));
+    }
+}
@@ -0,0 +1,121 @@
+use bevy::ecs::schedule::ScheduleLabel;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use crate::ascii_world::WorldSettings;
+use crate::net::NetMode;
+use crate::player::{apply_commands, PendingCommand, PlayerMarker};
+
+/// Energy an actor spends on one action.
+pub const ACTION_COST: i32 = 100;
+/// Speed of an ordinary actor, it acts once every tick.
+pub const NORMAL_SPEED: i32 = 100;
+/// Scheduler ticks per second in real time mode.
+pub const TICK_HZ: f64 = 10.;
+/// Ticks run in one frame while waiting for a player in turn based mode,
+/// so a world without ready players can't hang the game.
+const MAX_TICKS_PER_FRAME: usize = 1000;
+
+/// How the scheduler is driven, chosen when a world is created.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum TimeMode {
+    /// The world waits for the players, then runs until one of them may act again.
+    TurnBased,
+    /// The world runs at `TICK_HZ` whether the players act or not.
+    #[default]
+    RealTime,
+}
+
+/// Something that takes turns. Every tick it gains `speed` energy and may act
+/// once it has `ACTION_COST`.
+#[derive(Component, Debug, Clone, Copy)]
+pub struct Actor {
+    pub speed: i32,
+    pub energy: i32
+}
+impl Actor {
+    pub fn new(speed: i32) -> Self {
+        Self {
+            speed,
+            energy: 0
+        }
+    }
+    pub fn ready(&self) -> bool {
+        self.energy >= ACTION_COST
+    }
+    pub fn spend(&mut self) {
+        self.energy -= ACTION_COST;
+    }
+}
+
+/// One step of the scheduler, every system that acts for an `Actor` runs here.
+#[derive(ScheduleLabel, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Tick;
+
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub enum TickSet {
+    Energy,
+    Players,
+    Npcs,
+    /// Outcomes of the actions taken this tick: attacks, projectiles and deaths.
+    Resolve,
+}
+
+/// Ticks run since the world started.
+#[derive(Resource, Default)]
+pub struct TickCount(pub u64);
+
+fn grant_energy(
+    mut count: ResMut<TickCount>,
+    mut actors: Query<&mut Actor>,
+) {
+    count.0 += 1;
+    for mut actor in actors.iter_mut() {
+        // Ready actors that didn't act keep their turn without saving up more.
+        if !actor.ready() {
+            actor.energy += actor.speed;
+        }
+    }
+}
+
+fn run_real_time(world: &mut World) {
+    world.run_schedule(Tick);
+}
+
+fn run_turns(world: &mut World) {
+    let mut players = world.query_filtered::<(&Actor, &PendingCommand), With<PlayerMarker>>();
+    for _ in 0..MAX_TICKS_PER_FRAME {
+        let mut any = false;
+        let mut waiting = false;
+        for (actor, pending) in players.iter(world) {
+            any = true;
+            waiting |= actor.ready() && pending.0.is_none();
+        }
+        if !any || waiting {
+            break;
+        }
+        world.run_schedule(Tick);
+    }
+}
+
+fn time_mode_is(mode: TimeMode) -> impl Fn(Res<WorldSettings>) -> bool {
+    move |settings: Res<WorldSettings>| settings.time_mode == mode
+}
+
+pub struct TurnPlugin;
+impl Plugin for TurnPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<TickCount>()
+            .insert_resource(Time::<Fixed>::from_hz(TICK_HZ))
+            .add_schedule(Schedule::new(Tick))
+            .configure_sets(Tick, (TickSet::Energy, TickSet::Players, TickSet::Npcs, TickSet::Resolve).chain())
+            .add_systems(Tick, grant_energy.in_set(TickSet::Energy))
+            .add_systems(Tick, apply_commands.in_set(TickSet::Players))
+            .add_systems(FixedUpdate, run_real_time
+                .run_if(time_mode_is(TimeMode::RealTime))
+                .run_if(not(resource_equals(NetMode::Client))))
+            .add_systems(Update, run_turns
+                .run_if(time_mode_is(TimeMode::TurnBased))
+                .run_if(not(resource_equals(NetMode::Client))));
+    }
+}
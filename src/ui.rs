@@ -1,18 +1,71 @@
-use bevy::math::{vec2, vec3};
+use bevy::math::vec3;
 use bevy::prelude::*;
 use bevy_fast_tilemap::{Map, MapBundleManaged};
-use crate::ascii_render::{AsciiAtlas, UserData};
+use crate::ascii_render::{AsciiAtlas, UserData, TILE_SIZE};
+use crate::ascii_world::WorldSettings;
+use crate::chunk::CHUNK_SIZE;
+use crate::config::{list_atlases, Config, RESOLUTIONS};
+use crate::input::{Action, ActiveContext, ActionState, InputContext};
 use crate::MainState;
 use std::convert::TryFrom;
 use std::time::Duration;
 use bevy::app::AppExit;
 use bevy::prelude::KeyCode::KeyC;
 use rand::Rng;
+use crate::net::ConnectEvent;
+use crate::rng::GameRng;
+use crate::save::{list_saves, most_recent_save, LoadEvent};
+use crate::turn::TimeMode;
+use crate::world_map::{seed_from_str, NewWorldEvent};
+use std::path::PathBuf;
+use bevy::window::ReceivedCharacter;
+
+pub(crate) const BANNER: [&str; 10] = [
+    " ,ggg,         gg                                                  ",
+    "dP\"\"Y8a        88              ,dPYb,                         I8   ",
+    "Yb, `88        88              IP'`Yb                         I8   ",
+    "`\"\"  88        88              I8  8I                      88888888",
+    "     88        88              I8  8'                         I8   ",
+    "     88        88    ,gggg,gg  I8 dPgg,    ,ggg,,ggg,,ggg,    I8   ",
+    "     88        88   dP\"  \"Y8I  I8dP\" \"8I  ,8\" \"8P\" \"8P\" \"8,   I8   ",
+    "     88        88  i8'    ,8I  I8P    I8  I8   8I   8I   8I  ,I8,  ",
+    "     Y8b,____,d88,,d8,   ,d8b,,d8     I8,,dP   8I   8I   Yb,,d88b, ",
+    "      \"Y888888P\"Y8P\"Y8888P\"`Y888P     `Y88P'   8I   8I   `Y88P\"\"Y8 ",
+];
+const MENU_ROW: &str = "     Continue     Connect     New      Load      Setting    Exit   ";
 
 #[derive(Component)]
 struct UpdateTime(Timer);
 #[derive(Component)]
 struct BannerTiles(Vec<UVec2>);
+#[derive(Component)]
+struct MainMenuMarker;
+#[derive(Component)]
+struct LoadMenu {
+    saves: Vec<PathBuf>,
+    selected: usize
+}
+const NEW_WORLD_FIELDS: [&str; 3] = ["Name", "Seed", "Depth"];
+#[derive(Component)]
+struct NewWorldMenu {
+    values: [String; 3],
+    time_mode: TimeMode,
+    selected: usize
+}
+const SETTINGS_ROWS: usize = 7;
+#[derive(Component)]
+struct SettingsMenu {
+    atlases: Vec<String>,
+    selected: usize
+}
+#[derive(States, Debug, Clone, PartialEq, Eq, Hash, Default)]
+enum MenuScreen {
+    #[default]
+    Main,
+    Load,
+    NewWorld,
+    Settings
+}
 #[derive(States, Debug, Clone, PartialEq, Eq, Hash, Default)]
 enum MainMenuState {
     #[default]
@@ -27,10 +80,13 @@ enum MainMenuState {
 fn draw_selected(
     state: Res<State<MainMenuState>>,
     mut materials: ResMut<Assets<Map<UserData>>>,
-    maps: Query<&Handle<Map<UserData>>>,
+    maps: Query<&Handle<Map<UserData>>, With<MainMenuMarker>>,
 ) {
-    let tiles = "     Continue     Connect     New      Load      Setting    Exit   ".chars().collect::<Vec<_>>();
-    let map = materials.get_mut(maps.get_single().unwrap()).unwrap();
+    let tiles = MENU_ROW.chars().collect::<Vec<_>>();
+    let Ok(map_handle) = maps.get_single() else {
+        return;
+    };
+    let map = materials.get_mut(map_handle).unwrap();
     let mut m = map.indexer_mut();
     let y = 11u32;
     let ft_color = Color::BLUE;
@@ -73,26 +129,31 @@ fn draw_selected(
 }
 
 fn confirm_selected(
-    key: Res<ButtonInput<KeyCode>>,
+    actions: Res<ActionState>,
     state: Res<State<MainMenuState>>,
+    mut next_screen: ResMut<NextState<MenuScreen>>,
+    mut load: EventWriter<LoadEvent>,
+    mut connect: EventWriter<ConnectEvent>,
     mut exit: EventWriter<AppExit>
 ) {
-    if key.just_pressed(KeyCode::Enter) {
+    if actions.just_pressed(Action::MenuConfirm) {
         match state.get() {
             MainMenuState::Continue => {
-
+                if let Some(path) = most_recent_save() {
+                    load.send(LoadEvent(path));
+                }
             }
             MainMenuState::Connect => {
-
+                connect.send(ConnectEvent);
             }
             MainMenuState::New => {
-
+                next_screen.set(MenuScreen::NewWorld);
             }
             MainMenuState::Load => {
-
+                next_screen.set(MenuScreen::Load);
             }
             MainMenuState::Setting => {
-
+                next_screen.set(MenuScreen::Settings);
             }
             MainMenuState::Exit => {
                 exit.send(AppExit);
@@ -103,11 +164,11 @@ fn confirm_selected(
 }
 
 fn change_selected(
-    key: Res<ButtonInput<KeyCode>>,
+    actions: Res<ActionState>,
     mut state: ResMut<State<MainMenuState>>,
     mut next_state: ResMut<NextState<MainMenuState>>,
 ) {
-    if key.just_pressed(KeyCode::Tab) || key.just_pressed(KeyCode::KeyD) || key.just_pressed(KeyCode::ArrowRight) {
+    if actions.just_pressed(Action::MenuNext) {
         match state.get() {
             MainMenuState::Continue => {
                 next_state.set(MainMenuState::Connect);
@@ -129,7 +190,7 @@ fn change_selected(
             }
         }
     }
-    if key.just_pressed(KeyCode::KeyA) || key.just_pressed(KeyCode::ArrowLeft) {
+    if actions.just_pressed(Action::MenuPrevious) {
         match state.get() {
             MainMenuState::Continue => {
                 next_state.set(MainMenuState::Exit);
@@ -155,9 +216,10 @@ fn change_selected(
 
 fn banner_effect(
     mut materials: ResMut<Assets<Map<UserData>>>,
-    maps: Query<&Handle<Map<UserData>>>,
+    maps: Query<&Handle<Map<UserData>>, With<MainMenuMarker>>,
     mut q: Query<(&mut UpdateTime, &mut BannerTiles)>,
     time: Res<Time>,
+    mut rng: ResMut<GameRng>,
 ) {
     if let Ok((mut timer, mut tiles)) = q.get_single_mut() {
         timer.0.tick(time.delta());
@@ -171,7 +233,6 @@ fn banner_effect(
                 Color::PURPLE,
                 Color::ORANGE,
             ];
-            let mut rng = rand::thread_rng();
             let map = materials.get_mut(maps.get_single().unwrap()).unwrap();
             let mut m = map.indexer_mut();
             let r_pos = tiles.0[rng.gen_range(0..tiles.0.len())];
@@ -191,28 +252,18 @@ fn draw_main_menu(
     mut materials: ResMut<Assets<Map<crate::ascii_render::UserData>>>,
     mut commands: Commands
 ) {
-    let banner = [
-        " ,ggg,         gg                                                  ".chars().collect::<Vec<_>>(),
-        "dP\"\"Y8a        88              ,dPYb,                         I8   ".chars().collect::<Vec<_>>(),
-        "Yb, `88        88              IP'`Yb                         I8   ".chars().collect::<Vec<_>>(),
-        "`\"\"  88        88              I8  8I                      88888888".chars().collect::<Vec<_>>(),
-        "     88        88              I8  8'                         I8   ".chars().collect::<Vec<_>>(),
-        "     88        88    ,gggg,gg  I8 dPgg,    ,ggg,,ggg,,ggg,    I8   ".chars().collect::<Vec<_>>(),
-        "     88        88   dP\"  \"Y8I  I8dP\" \"8I  ,8\" \"8P\" \"8P\" \"8,   I8   ".chars().collect::<Vec<_>>(),
-        "     88        88  i8'    ,8I  I8P    I8  I8   8I   8I   8I  ,I8,  ".chars().collect::<Vec<_>>(),
-        "     Y8b,____,d88,,d8,   ,d8b,,d8     I8,,dP   8I   8I   Yb,,d88b, ".chars().collect::<Vec<_>>(),
-        "      \"Y888888P\"Y8P\"Y8888P\"`Y888P     `Y88P'   8I   8I   `Y88P\"\"Y8 ".chars().collect::<Vec<_>>(),
-        "                                                                   ".chars().collect::<Vec<_>>(),
-        "     Continue     Connect     New      Load      Setting    Exit   ".chars().collect::<Vec<_>>(),
-        "                                                                   ".chars().collect::<Vec<_>>(),
-    ];
+    let blank = " ".repeat(MENU_ROW.len());
+    let banner = BANNER.iter().copied()
+        .chain([blank.as_str(), MENU_ROW, blank.as_str()])
+        .map(|row| row.chars().collect::<Vec<_>>())
+        .collect::<Vec<_>>();
 
     let mut banner_tiles = vec![];
 
     let map = Map::<crate::ascii_render::UserData>::builder(
         UVec2::new(67, 13),
         ascii_atlas.0.clone(),
-        vec2(16., 16.),
+        Vec2::splat(TILE_SIZE),
     )
         .with_user_data(crate::ascii_render::UserData { alpha: 1.})
         .build_and_initialize(
@@ -234,18 +285,410 @@ fn draw_main_menu(
         ..default()
     })
         .insert(UpdateTime(Timer::new(Duration::from_millis(50), TimerMode::Repeating)))
-        .insert(BannerTiles(banner_tiles));
+        .insert(BannerTiles(banner_tiles))
+        .insert(MainMenuMarker);
+}
+
+fn set_main_menu_visibility<const VISIBLE: bool>(
+    mut menu: Query<&mut Visibility, With<MainMenuMarker>>
+) {
+    for mut visibility in menu.iter_mut() {
+        *visibility = if VISIBLE { Visibility::Inherited } else { Visibility::Hidden };
+    }
+}
+
+fn blank_menu_map(ascii_atlas: &AsciiAtlas) -> Map<UserData> {
+    blank_map(ascii_atlas, UVec2::new(67, 13), Color::NONE)
+}
+
+/// A map of `size` spaces on `bg`, for screens that write their text in later.
+pub(crate) fn blank_map(ascii_atlas: &AsciiAtlas, size: UVec2, bg: Color) -> Map<UserData> {
+    Map::<UserData>::builder(size, ascii_atlas.0.clone(), Vec2::splat(TILE_SIZE))
+        .with_user_data(UserData { alpha: 1. })
+        .build_and_initialize(|m| {
+            for y in 0..m.size().y {
+                for x in 0..m.size().x {
+                    m.set(x, y, ' ' as u32, Color::WHITE, bg);
+                }
+            }
+        })
+}
+
+/// Spawns `map` as a child of the camera, so it stays in place while the view pans and
+/// zooms. `z` orders it among the other panels, all of them in front of the world.
+pub(crate) fn spawn_panel(
+    commands: &mut Commands,
+    materials: &mut Assets<Map<UserData>>,
+    camera: Entity,
+    map: Map<UserData>,
+    z: f32,
+    marker: impl Bundle,
+) -> Entity {
+    let panel = commands.spawn(MapBundleManaged::<UserData> {
+        material: materials.add(map),
+        transform: Transform::default().with_translation(vec3(0., 0., z)),
+        ..default()
+    })
+        .insert(marker)
+        .id();
+    commands.entity(camera).add_child(panel);
+    panel
+}
+
+pub(crate) fn write_row(map: &mut Map<UserData>, y: u32, text: &str, ft_color: Color, bg_color: Color) {
+    let mut m = map.indexer_mut();
+    let chars = text.chars().collect::<Vec<_>>();
+    for x in 0..m.size().x {
+        let tile = chars.get(x as usize).copied().unwrap_or(' ') as u32;
+        m.set(x, y, tile, ft_color, bg_color);
+    }
+}
+
+fn set_text_context<const TEXT: bool>(
+    mut context: ResMut<ActiveContext>,
+    state: Res<State<MainState>>
+) {
+    if TEXT {
+        context.0 = InputContext::Text;
+    } else if *state.get() == MainState::MainMenu {
+        context.0 = InputContext::Menu;
+    }
+}
+
+fn draw_load_menu(
+    ascii_atlas: Res<AsciiAtlas>,
+    mut materials: ResMut<Assets<Map<UserData>>>,
+    mut commands: Commands
+) {
+    commands.spawn(MapBundleManaged::<UserData> {
+        material: materials.add(blank_menu_map(&ascii_atlas)),
+        transform: Transform::default().with_translation(vec3(0., 0., 0.)),
+        ..default()
+    })
+        .insert(LoadMenu {
+            saves: list_saves(),
+            selected: 0
+        });
+}
+
+fn draw_load_selected(
+    mut materials: ResMut<Assets<Map<UserData>>>,
+    menu: Query<(&Handle<Map<UserData>>, &LoadMenu), Changed<LoadMenu>>,
+) {
+    if let Ok((map_handle, menu)) = menu.get_single() {
+        let map = materials.get_mut(map_handle).unwrap();
+        let rows = map.indexer_mut().size().y - 2;
+        write_row(map, 0, "     Load world            Enter: load   Esc: back", Color::WHITE, Color::NONE);
+        let first = menu.selected.saturating_sub(rows as usize - 1);
+        for row in 0..rows {
+            let i = first + row as usize;
+            let name = menu.saves.get(i)
+                .and_then(|path| path.file_stem())
+                .map(|name| format!("     {}", name.to_string_lossy()))
+                .unwrap_or_default();
+            if i == menu.selected && i < menu.saves.len() {
+                write_row(map, row + 2, &name, Color::BLUE, Color::WHITE);
+            } else {
+                write_row(map, row + 2, &name, Color::WHITE, Color::NONE);
+            }
+        }
+        if menu.saves.is_empty() {
+            write_row(map, 2, "     No saved worlds", Color::GRAY, Color::NONE);
+        }
+    }
+}
+
+fn load_menu_input(
+    actions: Res<ActionState>,
+    mut menu: Query<&mut LoadMenu>,
+    mut next_screen: ResMut<NextState<MenuScreen>>,
+    mut load: EventWriter<LoadEvent>,
+) {
+    if let Ok(mut menu) = menu.get_single_mut() {
+        let len = menu.saves.len();
+        if actions.just_pressed(Action::MenuBack) {
+            next_screen.set(MenuScreen::Main);
+        }
+        if len == 0 {
+            return;
+        }
+        if actions.just_pressed(Action::MenuDown) {
+            menu.selected = (menu.selected + 1) % len;
+        }
+        if actions.just_pressed(Action::MenuUp) {
+            menu.selected = (menu.selected + len - 1) % len;
+        }
+        if actions.just_pressed(Action::MenuConfirm) {
+            load.send(LoadEvent(menu.saves[menu.selected].clone()));
+        }
+    }
+}
+
+fn despawn_load_menu(
+    mut commands: Commands,
+    menu: Query<Entity, With<LoadMenu>>
+) {
+    for entity in menu.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn draw_new_world_menu(
+    ascii_atlas: Res<AsciiAtlas>,
+    settings: Res<WorldSettings>,
+    mut rng: ResMut<GameRng>,
+    mut materials: ResMut<Assets<Map<UserData>>>,
+    mut commands: Commands
+) {
+    commands.spawn(MapBundleManaged::<UserData> {
+        material: materials.add(blank_menu_map(&ascii_atlas)),
+        transform: Transform::default().with_translation(vec3(0., 0., 0.)),
+        ..default()
+    })
+        .insert(NewWorldMenu {
+            values: [
+                String::from("world"),
+                rng.gen::<u32>().to_string(),
+                settings.depth.to_string(),
+            ],
+            time_mode: settings.time_mode,
+            selected: 0
+        });
+}
+
+fn draw_new_world_selected(
+    mut materials: ResMut<Assets<Map<UserData>>>,
+    menu: Query<(&Handle<Map<UserData>>, &NewWorldMenu), Changed<NewWorldMenu>>,
+) {
+    if let Ok((map_handle, menu)) = menu.get_single() {
+        let map = materials.get_mut(map_handle).unwrap();
+        write_row(map, 0, "     Create world          Enter: create   Esc: back", Color::WHITE, Color::NONE);
+        for (i, label) in NEW_WORLD_FIELDS.iter().enumerate() {
+            let y = 2 + i as u32;
+            if i == menu.selected {
+                write_row(map, y, &format!("     {:<8}{}_", label, menu.values[i]), Color::BLUE, Color::WHITE);
+            } else {
+                write_row(map, y, &format!("     {:<8}{}", label, menu.values[i]), Color::WHITE, Color::NONE);
+            }
+        }
+        let time = match menu.time_mode {
+            TimeMode::TurnBased => "     Time    < turn based >",
+            TimeMode::RealTime => "     Time    < real time >",
+        };
+        if menu.selected == NEW_WORLD_FIELDS.len() {
+            write_row(map, 5, time, Color::BLUE, Color::WHITE);
+        } else {
+            write_row(map, 5, time, Color::WHITE, Color::NONE);
+        }
+        if menu.selected == NEW_WORLD_FIELDS.len() + 1 {
+            write_row(map, 6, "     Create", Color::BLUE, Color::WHITE);
+        } else {
+            write_row(map, 6, "     Create", Color::WHITE, Color::NONE);
+        }
+    }
+}
+
+fn new_world_menu_input(
+    actions: Res<ActionState>,
+    mut chars: EventReader<ReceivedCharacter>,
+    mut menu: Query<&mut NewWorldMenu>,
+    mut next_screen: ResMut<NextState<MenuScreen>>,
+    mut new_world: EventWriter<NewWorldEvent>,
+) {
+    let Ok(mut menu) = menu.get_single_mut() else {
+        return;
+    };
+    let len = NEW_WORLD_FIELDS.len() + 2;
+    if actions.just_pressed(Action::MenuBack) {
+        next_screen.set(MenuScreen::Main);
+        return;
+    }
+    if actions.just_pressed(Action::MenuDown) {
+        menu.selected = (menu.selected + 1) % len;
+    }
+    if actions.just_pressed(Action::MenuUp) {
+        menu.selected = (menu.selected + len - 1) % len;
+    }
+    let selected = menu.selected;
+    if selected < NEW_WORLD_FIELDS.len() {
+        for ev in chars.read() {
+            for c in ev.char.chars() {
+                let numeric = selected >= 2;
+                if c.is_control() || (numeric && !c.is_ascii_digit()) || menu.values[selected].len() >= 40 {
+                    continue;
+                }
+                menu.values[selected].push(c);
+            }
+        }
+        if actions.just_pressed(Action::TextDelete) {
+            menu.values[selected].pop();
+        }
+    } else {
+        chars.clear();
+        if selected == NEW_WORLD_FIELDS.len() && (actions.just_pressed(Action::MenuNext) || actions.just_pressed(Action::MenuPrevious)) {
+            menu.time_mode = match menu.time_mode {
+                TimeMode::TurnBased => TimeMode::RealTime,
+                TimeMode::RealTime => TimeMode::TurnBased,
+            };
+        }
+    }
+    if actions.just_pressed(Action::MenuConfirm) {
+        // Whole chunks deep, the world has no edges sideways.
+        let layers = CHUNK_SIZE.z as u32;
+        let depth = menu.values[2].parse::<u32>().unwrap_or(layers).clamp(layers, 128) / layers * layers;
+        let name = menu.values[0].trim();
+        new_world.send(NewWorldEvent {
+            name: if name.is_empty() { String::from("world") } else { name.to_string() },
+            seed: seed_from_str(&menu.values[1]),
+            depth,
+            time_mode: menu.time_mode,
+        });
+    }
+}
+
+fn despawn_new_world_menu(
+    mut commands: Commands,
+    menu: Query<Entity, With<NewWorldMenu>>
+) {
+    for entity in menu.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn draw_settings_menu(
+    ascii_atlas: Res<AsciiAtlas>,
+    mut materials: ResMut<Assets<Map<UserData>>>,
+    mut commands: Commands
+) {
+    commands.spawn(MapBundleManaged::<UserData> {
+        material: materials.add(blank_menu_map(&ascii_atlas)),
+        transform: Transform::default().with_translation(vec3(0., 0., 0.)),
+        ..default()
+    })
+        .insert(SettingsMenu {
+            atlases: list_atlases(),
+            selected: 0
+        });
+}
+
+fn draw_settings_selected(
+    config: Res<Config>,
+    mut materials: ResMut<Assets<Map<UserData>>>,
+    menu: Query<(&Handle<Map<UserData>>, Ref<SettingsMenu>)>,
+) {
+    if let Ok((map_handle, menu)) = menu.get_single() {
+        if !menu.is_changed() && !config.is_changed() {
+            return;
+        }
+        let map = materials.get_mut(map_handle).unwrap();
+        let on_off = |b: bool| if b { "on" } else { "off" };
+        let rows = [
+            format!("Resolution   {}x{}", config.resolution[0], config.resolution[1]),
+            format!("Fullscreen   {}", on_off(config.fullscreen)),
+            format!("VSync        {}", on_off(config.vsync)),
+            format!("Glyph scale  {:.2}", config.glyph_scale),
+            format!("Move speed   {}", config.movement_speed),
+            format!("Atlas        {} (next start)", config.atlas),
+            format!("Follow layer {}", on_off(config.follow_player)),
+        ];
+        write_row(map, 0, "     Settings        Left/Right: change   Esc: back", Color::WHITE, Color::NONE);
+        for (i, row) in rows.iter().enumerate() {
+            let text = format!("     {}", row);
+            if i == menu.selected {
+                write_row(map, 2 + i as u32, &text, Color::BLUE, Color::WHITE);
+            } else {
+                write_row(map, 2 + i as u32, &text, Color::WHITE, Color::NONE);
+            }
+        }
+    }
+}
+
+fn settings_menu_input(
+    actions: Res<ActionState>,
+    mut config: ResMut<Config>,
+    mut menu: Query<&mut SettingsMenu>,
+    mut next_screen: ResMut<NextState<MenuScreen>>,
+) {
+    let Ok(mut menu) = menu.get_single_mut() else {
+        return;
+    };
+    if actions.just_pressed(Action::MenuBack) {
+        next_screen.set(MenuScreen::Main);
+        return;
+    }
+    if actions.just_pressed(Action::MenuDown) {
+        menu.selected = (menu.selected + 1) % SETTINGS_ROWS;
+    }
+    if actions.just_pressed(Action::MenuUp) {
+        menu.selected = (menu.selected + SETTINGS_ROWS - 1) % SETTINGS_ROWS;
+    }
+    let step: i32 = if actions.just_pressed(Action::MenuNext) {
+        1
+    } else if actions.just_pressed(Action::MenuPrevious) {
+        -1
+    } else {
+        return;
+    };
+    let cycle = |i: usize, len: usize| (i as i32 + step).rem_euclid(len as i32) as usize;
+    match menu.selected {
+        0 => {
+            let current = RESOLUTIONS.iter().position(|r| *r == config.resolution).unwrap_or(0);
+            config.resolution = RESOLUTIONS[cycle(current, RESOLUTIONS.len())];
+        }
+        1 => config.fullscreen = !config.fullscreen,
+        2 => config.vsync = !config.vsync,
+        3 => config.glyph_scale = (config.glyph_scale + 0.25 * step as f32).clamp(0.5, 4.),
+        4 => config.movement_speed = (config.movement_speed + step as f32).clamp(1., 100.),
+        6 => config.follow_player = !config.follow_player,
+        _ => {
+            if !menu.atlases.is_empty() {
+                let current = menu.atlases.iter().position(|a| *a == config.atlas).unwrap_or(0);
+                config.atlas = menu.atlases[cycle(current, menu.atlases.len())].clone();
+            }
+        }
+    }
+}
+
+fn despawn_settings_menu(
+    mut commands: Commands,
+    menu: Query<Entity, With<SettingsMenu>>
+) {
+    for entity in menu.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn despawn_main_menu(
+    mut commands: Commands,
+    menu: Query<Entity, Or<(With<MainMenuMarker>, With<LoadMenu>, With<NewWorldMenu>, With<SettingsMenu>)>>,
+    mut next_screen: ResMut<NextState<MenuScreen>>,
+) {
+    for entity in menu.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    next_screen.set(MenuScreen::Main);
 }
 
-pub(crate) struct UiPlugin;
+pub struct UiPlugin;
 impl Plugin for UiPlugin {
     fn build(&self, app: &mut App) {
         app
             .init_state::<MainMenuState>()
+            .init_state::<MenuScreen>()
             .add_systems(OnEnter(MainState::MainMenu), draw_main_menu)
+            .add_systems(OnExit(MainState::MainMenu), despawn_main_menu)
             .add_systems(Update, draw_selected.run_if(state_changed::<MainMenuState>))
             .add_systems(Update, banner_effect.run_if(in_state(MainState::MainMenu)))
-            .add_systems(Update, change_selected.run_if(in_state(MainState::MainMenu)))
-            .add_systems(Update, confirm_selected.run_if(in_state(MainState::MainMenu)));
+            .add_systems(Update, change_selected.run_if(in_state(MainState::MainMenu)).run_if(in_state(MenuScreen::Main)))
+            .add_systems(Update, confirm_selected.run_if(in_state(MainState::MainMenu)).run_if(in_state(MenuScreen::Main)))
+            .add_systems(OnEnter(MenuScreen::Load), (set_main_menu_visibility::<false>, draw_load_menu))
+            .add_systems(OnExit(MenuScreen::Load), (set_main_menu_visibility::<true>, despawn_load_menu))
+            .add_systems(Update, (load_menu_input, draw_load_selected).run_if(in_state(MenuScreen::Load)))
+            .add_systems(OnEnter(MenuScreen::NewWorld), (set_main_menu_visibility::<false>, set_text_context::<true>, draw_new_world_menu))
+            .add_systems(OnExit(MenuScreen::NewWorld), (set_main_menu_visibility::<true>, set_text_context::<false>, despawn_new_world_menu))
+            .add_systems(Update, (new_world_menu_input, draw_new_world_selected).run_if(in_state(MenuScreen::NewWorld)))
+            .add_systems(OnEnter(MenuScreen::Settings), (set_main_menu_visibility::<false>, draw_settings_menu))
+            .add_systems(OnExit(MenuScreen::Settings), (set_main_menu_visibility::<true>, despawn_settings_menu))
+            .add_systems(Update, (settings_menu_input, draw_settings_selected).chain().run_if(in_state(MenuScreen::Settings)));
     }
 }
\ No newline at end of file
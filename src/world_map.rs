@@ -1,14 +1,237 @@
 use bevy::prelude::*;
+use noise::{Fbm, MultiFractal, NoiseFn, Perlin};
+use crate::ascii_world::{AsciiAddEvent, AsciiTile, Grid, Terrain, WorldSettings};
+use crate::chunk::{Chunk, ChunkSet, ChunkStore, CHUNK_SIZE};
+use crate::MainState;
+use crate::player::spawn_player;
+use crate::rng::GameRng;
+use crate::save::SaveSlot;
+use crate::turn::{TickCount, TimeMode, TICK_HZ};
+
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct WorldGenSet;
+
+#[derive(Resource, Clone)]
+pub struct WorldGen {
+    pub seed: u32,
+    /// Fraction of the world height around which the surface is placed.
+    pub ground_level: f64,
+    /// Fraction of the world height the surface may deviate from `ground_level`.
+    pub relief: f64,
+    /// Fraction of the world height below which open surface cells are flooded.
+    pub water_level: f64,
+    pub dirt_depth: u32,
+    pub cave_threshold: f64,
+}
+impl FromWorld for WorldGen {
+    fn from_world(_world: &mut World) -> Self {
+        Self {
+            seed: 0,
+            ground_level: 0.5,
+            relief: 0.2,
+            water_level: 0.45,
+            dirt_depth: 3,
+            cave_threshold: 0.08,
+        }
+    }
+}
+
+/// Replaces the current world with a freshly generated one and enters the game.
+#[derive(Event, Clone)]
+pub struct NewWorldEvent {
+    pub name: String,
+    pub seed: u32,
+    pub depth: u32,
+    pub time_mode: TimeMode,
+}
+/// Sent in the frame `create_world` acts on a `NewWorldEvent`, which may be after the one
+/// it was sent in.
+#[derive(Event)]
+pub struct WorldCreatedEvent(pub NewWorldEvent);
+
+/// Numeric seeds are used as is, any other text is hashed so it can be typed in as a word.
+pub fn seed_from_str(text: &str) -> u32 {
+    text.trim().parse().unwrap_or_else(|_| {
+        text.bytes().fold(0x811c9dc5u32, |hash, b| (hash ^ b as u32).wrapping_mul(0x01000193))
+    })
+}
+
+/// The noise fields a world is generated from.
+struct Noise {
+    height: Fbm<Perlin>,
+    cave: Fbm<Perlin>,
+    bedrock: Perlin,
+}
+impl Noise {
+    fn new(seed: u32) -> Self {
+        Self {
+            height: Fbm::<Perlin>::new(seed)
+                .set_octaves(5)
+                .set_frequency(1. / 48.),
+            cave: Fbm::<Perlin>::new(seed.wrapping_add(1))
+                .set_octaves(2)
+                .set_frequency(1. / 16.),
+            bedrock: Perlin::new(seed.wrapping_add(2)),
+        }
+    }
+}
+
+impl WorldGen {
+    /// Layer of the topmost ground cell of the column at `x`, `y`.
+    fn height(&self, noise: &Noise, depth: u32, x: i32, y: i32) -> i32 {
+        let top = depth.saturating_sub(2) as f64;
+        let n = noise.height.get([x as f64, y as f64]);
+        (depth as f64 * (self.ground_level + self.relief * n)).clamp(2., top) as i32
+    }
+    fn water_level(&self, depth: u32) -> i32 {
+        (depth as f64 * self.water_level) as i32
+    }
+    /// What `Grid::surface` finds in the column at `x`, `y` of the freshly generated world,
+    /// without the column having to be loaded.
+    pub fn surface(&self, depth: u32, x: i32, y: i32) -> IVec3 {
+        let height = self.height(&Noise::new(self.seed), depth, x, y);
+        IVec3::new(x, y, (height.max(self.water_level(depth)) + 1).min(depth as i32 - 1))
+    }
+}
+
+/// Where players are put into the world, on the ground near the column at `x`, `y`.
+pub fn spawn_point(grid: &Grid, gen: &WorldGen, x: i32, y: i32) -> IVec3 {
+    grid.surface(x, y).unwrap_or_else(|| gen.surface(grid.depth(), x, y))
+}
+
+/// Generates the terrain of the chunk at `chunk` from the seed alone, so every chunk comes
+/// out the same no matter which chunks were generated before it.
+pub fn generate_chunk(gen: &WorldGen, depth: u32, chunk: IVec3) -> Chunk {
+    let noise = Noise::new(gen.seed);
+    let origin = chunk * CHUNK_SIZE;
+    let water_level = gen.water_level(depth);
+    // One column past every edge too, climbs depend on the neighbouring heights.
+    let columns = CHUNK_SIZE.truncate() + IVec2::splat(2);
+    let heights = (0..columns.y)
+        .flat_map(|y| (0..columns.x).map(move |x| IVec2::new(x, y)))
+        .map(|p| gen.height(&noise, depth, origin.x + p.x - 1, origin.y + p.y - 1))
+        .collect::<Vec<_>>();
+    let height_at = |local: IVec2| heights[((local.y + 1) * columns.x + local.x + 1) as usize];
+
+    let mut terrain = Chunk::default();
+    for y in 0..CHUNK_SIZE.y {
+        for x in 0..CHUNK_SIZE.x {
+            let (wx, wy) = (origin.x + x, origin.y + y);
+            let height = height_at(IVec2::new(x, y));
+            let bedrock = if noise.bedrock.get([wx as f64 / 4., wy as f64 / 4.]) > 0. { 1 } else { 0 };
+            for z in 0..CHUNK_SIZE.z {
+                let wz = origin.z + z;
+                let cell = if wz <= bedrock {
+                    Terrain::Bedrock
+                } else if wz > height {
+                    if wz <= water_level { Terrain::Water } else { Terrain::Air }
+                } else if wz < height && noise.cave.get([wx as f64, wy as f64, wz as f64 * 2.]).abs() < gen.cave_threshold {
+                    Terrain::Air
+                } else if wz == height {
+                    if wz < water_level { Terrain::Dirt } else { Terrain::Grass }
+                } else if wz + gen.dirt_depth as i32 > height {
+                    Terrain::Dirt
+                } else {
+                    Terrain::Stone
+                };
+                terrain.set(IVec3::new(x, y, z), cell);
+            }
+        }
+    }
+    place_climbs(&mut terrain, origin, depth as i32, water_level, height_at);
+    terrain
+}
+
+/// Puts stairs at the foot of one layer steps in the surface and ladders up higher cliffs,
+/// so every dry part of the surface can be reached.
+fn place_climbs(chunk: &mut Chunk, origin: IVec3, depth: i32, water_level: i32, height_at: impl Fn(IVec2) -> i32) {
+    let layers = origin.z..origin.z + CHUNK_SIZE.z;
+    for y in 0..CHUNK_SIZE.y {
+        for x in 0..CHUNK_SIZE.x {
+            let here = IVec2::new(x, y);
+            let height = height_at(here);
+            let stand = height + 1;
+            let highest = [IVec2::X, IVec2::NEG_X, IVec2::Y, IVec2::NEG_Y].iter()
+                .map(|d| height_at(here + *d))
+                .max()
+                .unwrap_or(0);
+            let climb = if highest == stand {
+                stand..=stand
+            } else if highest > stand {
+                stand..=highest.min(depth - 1)
+            } else {
+                continue;
+            };
+            // Flooded columns are swum up instead.
+            if stand <= water_level {
+                continue;
+            }
+            let terrain = if highest == stand { Terrain::StairsUp } else { Terrain::Ladder };
+            for z in climb.filter(|z| layers.contains(z)) {
+                let local = here.extend(z - origin.z);
+                if chunk.get(local) == Terrain::Air {
+                    chunk.set(local, terrain);
+                }
+            }
+        }
+    }
+}
 
 fn startup(
-    mut commands: Commands
+    mut grid: ResMut<Grid>,
+    settings: Res<WorldSettings>,
 ) {
-
+    if grid.depth() != settings.depth {
+        *grid = Grid::new(settings.depth);
+    }
+}
+fn create_world(
+    mut commands: Commands,
+    mut new_world: EventReader<NewWorldEvent>,
+    mut settings: ResMut<WorldSettings>,
+    mut gen: ResMut<WorldGen>,
+    mut grid: ResMut<Grid>,
+    mut store: ResMut<ChunkStore>,
+    mut slot: ResMut<SaveSlot>,
+    mut rng: ResMut<GameRng>,
+    mut ticks: ResMut<TickCount>,
+    mut fixed: ResMut<Time<Fixed>>,
+    tiles: Query<Entity, With<AsciiTile>>,
+    mut add: EventWriter<AsciiAddEvent>,
+    mut created: EventWriter<WorldCreatedEvent>,
+    mut next_state: ResMut<NextState<MainState>>,
+) {
+    let Some(ev) = new_world.read().last() else {
+        return;
+    };
+    for entity in tiles.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    slot.0 = ev.name.clone();
+    gen.seed = ev.seed;
+    settings.depth = ev.depth;
+    settings.time_mode = ev.time_mode;
+    *grid = Grid::new(settings.depth);
+    store.chunks.clear();
+    // Everything that decides how the world plays out starts over, so a replay of the
+    // same seed and input ends up in the same place.
+    rng.reseed(ev.seed as u64);
+    ticks.0 = 0;
+    *fixed = Time::<Fixed>::from_hz(TICK_HZ);
+    // The chunks around the player are generated and populated as they stream in.
+    spawn_player(&mut commands, spawn_point(&grid, &gen, 0, 0), &mut add);
+    created.send(WorldCreatedEvent(ev.clone()));
+    next_state.set(MainState::InGame);
 }
-pub(crate) struct WorldMapPlugin;
+
+pub struct WorldMapPlugin;
 impl Plugin for WorldMapPlugin {
     fn build(&self, app: &mut App) {
         app
-            .add_systems(Startup, startup);
+            .init_resource::<WorldGen>()
+            .add_event::<NewWorldEvent>()
+            .add_event::<WorldCreatedEvent>()
+            .add_systems(Startup, startup.in_set(WorldGenSet))
+            .add_systems(Update, create_world.before(ChunkSet).run_if(on_event::<NewWorldEvent>()));
     }
-}
\ No newline at end of file
+}
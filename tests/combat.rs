@@ -0,0 +1,34 @@
+mod common;
+
+use bevy::prelude::*;
+use uahmt::ascii_world::Terrain;
+use uahmt::combat::CombatEvent;
+use uahmt::living_entity::Archetype;
+use common::Harness;
+
+/// Walking into a hostile creature attacks it instead of moving.
+#[test]
+fn bumping_into_a_creature_attacks_it() {
+    let mut harness = Harness::new();
+    let start = harness.player_pos();
+    let player = harness.player();
+    // Walled in on every side but the player's, so it can neither run nor come round.
+    for offset in [IVec2::new(0, -1), IVec2::new(0, 1), IVec2::new(1, -1), IVec2::new(1, 1), IVec2::new(2, -1), IVec2::new(2, 0), IVec2::new(2, 1)] {
+        harness.set_terrain(start + offset.extend(0), Terrain::Wall);
+    }
+    let rat = harness.spawn_creature(Archetype::Rat, start + IVec3::X);
+    let died = |combat: &[CombatEvent]| combat.iter().any(|ev| matches!(ev, CombatEvent::Died { entity, .. } if *entity == rat));
+    // Once it is dead its corpse doesn't block the way any more.
+    for _ in 0..10 {
+        harness.tap(KeyCode::KeyD);
+        if died(&harness.combat) {
+            break;
+        }
+    }
+    assert!(died(&harness.combat), "{:?}", harness.combat);
+    assert!(harness.player_moves().is_empty(), "{:?}", harness.player_moves());
+    let attacks = harness.combat.iter()
+        .filter(|ev| matches!(ev, CombatEvent::Hit { attacker, target, .. } | CombatEvent::Missed { attacker, target } if *attacker == player && *target == rat))
+        .count();
+    assert!(attacks >= 2, "{:?}", harness.combat);
+}
@@ -0,0 +1,185 @@
+#![allow(dead_code)]
+
+use std::time::Duration;
+use bevy::ecs::event::ManualEventReader;
+use bevy::ecs::system::RunSystemOnce;
+use bevy::input::InputPlugin;
+use bevy::prelude::*;
+use bevy::time::TimeUpdateStrategy;
+use uahmt::ascii_world::{AsciiAddEvent, AsciiMoveEvent, AsciiRemoveEvent, AsciiTile, Grid, Terrain};
+use uahmt::chunk::{CHUNK_SIZE, LOAD_RADIUS};
+use uahmt::combat::CombatEvent;
+use uahmt::input::{InputMap, InputMapPlugin};
+use uahmt::item::{spawn_item, Inventory, Item};
+use uahmt::living_entity::{spawn_creature, Archetype};
+use uahmt::player::{PlayerMarker, PlayerPlugin};
+use uahmt::rng::GameRng;
+use uahmt::turn::TimeMode;
+use uahmt::world_map::NewWorldEvent;
+use uahmt::{MainState, SimulationPlugins};
+
+/// Every update advances time by exactly this much.
+pub const FRAME: Duration = Duration::from_nanos(16_666_667);
+pub const SEED: u32 = 1234;
+pub const DEPTH: u32 = 32;
+/// Cells around the player flattened into an empty floor before every test.
+pub const ARENA: i32 = 16;
+
+/// The simulation without a window, driven frame by frame with fixed time steps and
+/// synthetic key presses. Starts in a fresh world of `SEED`, with the player alone on a
+/// flat floor so nothing but the test decides where they go.
+pub struct Harness {
+    pub app: App,
+    reader: ManualEventReader<AsciiMoveEvent>,
+    combat_reader: ManualEventReader<CombatEvent>,
+    /// Every `AsciiMoveEvent` sent since the harness was set up.
+    pub moves: Vec<AsciiMoveEvent>,
+    /// Every `CombatEvent` sent since the harness was set up.
+    pub combat: Vec<CombatEvent>,
+}
+impl Harness {
+    pub fn new() -> Self {
+        let mut app = App::new();
+        app
+            .init_state::<MainState>()
+            .insert_resource(TimeUpdateStrategy::ManualDuration(FRAME))
+            // The defaults, rather than whatever bindings file lies around.
+            .insert_resource(InputMap::default())
+            .add_plugins(MinimalPlugins)
+            .add_plugins(InputPlugin)
+            .add_plugins(InputMapPlugin)
+            .add_plugins(SimulationPlugins)
+            .add_plugins(PlayerPlugin);
+        app.world.send_event(NewWorldEvent {
+            name: String::from("test"),
+            seed: SEED,
+            depth: DEPTH,
+            time_mode: TimeMode::RealTime,
+        });
+        let reader = app.world.resource::<Events<AsciiMoveEvent>>().get_reader();
+        let combat_reader = app.world.resource::<Events<CombatEvent>>().get_reader();
+        let mut harness = Self {
+            app,
+            reader,
+            combat_reader,
+            moves: Vec::new(),
+            combat: Vec::new(),
+        };
+        harness.load_world();
+        harness.clear_arena();
+        harness.moves.clear();
+        harness.combat.clear();
+        harness
+    }
+
+    /// Runs until every chunk around the player has streamed in.
+    fn load_world(&mut self) {
+        let columns = (2 * LOAD_RADIUS + 1) * (2 * LOAD_RADIUS + 1);
+        let chunks = (columns * DEPTH as i32 / CHUNK_SIZE.z) as usize;
+        for _ in 0..200 {
+            self.update();
+            if self.app.world.resource::<Grid>().chunks().count() >= chunks {
+                return;
+            }
+        }
+        panic!("the world never finished loading");
+    }
+
+    /// Removes everything but the player and puts them on a floor of stone with air above.
+    fn clear_arena(&mut self) {
+        let others = self.app.world
+            .query_filtered::<(Entity, &AsciiTile), Without<PlayerMarker>>()
+            .iter(&self.app.world)
+            .map(|(entity, tile)| (entity, tile.pos))
+            .collect::<Vec<_>>();
+        for (entity, pos) in others {
+            self.app.world.entity_mut(entity).despawn_recursive();
+            self.app.world.send_event(AsciiRemoveEvent::new(entity, pos));
+        }
+        let centre = self.player_pos();
+        for y in -ARENA..=ARENA {
+            for x in -ARENA..=ARENA {
+                let column = centre + IVec3::new(x, y, 0);
+                self.set_terrain(column - IVec3::Z, Terrain::Stone);
+                self.set_terrain(column, Terrain::Air);
+                self.set_terrain(column + IVec3::Z, Terrain::Air);
+            }
+        }
+        self.update();
+    }
+
+    pub fn update(&mut self) {
+        self.app.update();
+        let events = self.app.world.resource::<Events<AsciiMoveEvent>>();
+        self.moves.extend(self.reader.read(events).copied());
+        let events = self.app.world.resource::<Events<CombatEvent>>();
+        self.combat.extend(self.combat_reader.read(events).copied());
+    }
+    pub fn run_for(&mut self, duration: Duration) {
+        for _ in 0..duration.as_nanos().div_ceil(FRAME.as_nanos()) {
+            self.update();
+        }
+    }
+
+    pub fn press(&mut self, key: KeyCode) {
+        self.app.world.resource_mut::<ButtonInput<KeyCode>>().press(key);
+    }
+    pub fn release(&mut self, key: KeyCode) {
+        self.app.world.resource_mut::<ButtonInput<KeyCode>>().release(key);
+    }
+    /// Presses `key` for a single frame, one step or action, and gives the turn scheduler
+    /// a second to carry it out.
+    pub fn tap(&mut self, key: KeyCode) {
+        self.press(key);
+        self.update();
+        self.release(key);
+        self.run_for(Duration::from_secs(1));
+    }
+
+    pub fn player(&mut self) -> Entity {
+        self.app.world
+            .query_filtered::<Entity, With<PlayerMarker>>()
+            .single(&self.app.world)
+    }
+    pub fn player_pos(&mut self) -> IVec3 {
+        let player = self.player();
+        self.app.world.get::<AsciiTile>(player).unwrap().pos
+    }
+    /// The moves the player made since the harness was set up.
+    pub fn player_moves(&mut self) -> Vec<AsciiMoveEvent> {
+        let player = self.player();
+        self.moves.iter().filter(|ev| ev.entity == player).copied().collect()
+    }
+    /// What the player carries, in inventory order.
+    pub fn inventory(&mut self) -> Vec<Item> {
+        let player = self.player();
+        let inventory = self.app.world.get::<Inventory>(player).unwrap();
+        inventory.items.iter().map(|e| *self.app.world.get::<Item>(*e).unwrap()).collect()
+    }
+    /// The items lying at `pos`.
+    pub fn items_at(&mut self, pos: IVec3) -> Vec<Item> {
+        let grid = self.app.world.resource::<Grid>();
+        grid.occupants(pos).iter().filter_map(|e| self.app.world.get::<Item>(*e)).copied().collect()
+    }
+    pub fn terrain(&self, pos: IVec3) -> Option<Terrain> {
+        self.app.world.resource::<Grid>().terrain(pos)
+    }
+    pub fn set_terrain(&mut self, pos: IVec3, terrain: Terrain) {
+        self.app.world.resource_mut::<Grid>().set_terrain(pos, terrain);
+    }
+    /// Puts `item` on the ground at `pos`, where the next update places it on the grid.
+    pub fn spawn_item(&mut self, item: Item, pos: IVec3) -> Entity {
+        let entity = self.app.world.run_system_once(move |mut commands: Commands, mut add: EventWriter<AsciiAddEvent>| {
+            spawn_item(&mut commands, item, pos, &mut add)
+        });
+        self.update();
+        entity
+    }
+    pub fn spawn_creature(&mut self, archetype: Archetype, pos: IVec3) -> Entity {
+        let entity = self.app.world.run_system_once(move |mut commands: Commands, mut rng: ResMut<GameRng>, mut add: EventWriter<AsciiAddEvent>| {
+            spawn_creature(&mut commands, archetype, pos, &mut *rng, &mut add)
+        });
+        self.update();
+        entity
+    }
+}
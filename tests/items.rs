@@ -0,0 +1,34 @@
+mod common;
+
+use std::time::Duration;
+use bevy::prelude::*;
+use uahmt::item::{Item, ItemAction, ItemKind};
+use uahmt::player::PlayerCommand;
+use common::Harness;
+
+#[test]
+fn picks_up_and_drops_items() {
+    let mut harness = Harness::new();
+    let start = harness.player_pos();
+    harness.spawn_item(Item::new(ItemKind::Rock, 3), start);
+    harness.tap(KeyCode::KeyG);
+    assert_eq!(harness.inventory(), vec![Item::new(ItemKind::Rock, 3)]);
+    assert!(harness.items_at(start).is_empty());
+
+    harness.tap(KeyCode::KeyD);
+    // The inventory screen needs a renderer, this is the command it sends for Enter.
+    harness.app.world.send_event(PlayerCommand {
+        item: Some(ItemAction::Drop(0)),
+        ..default()
+    });
+    harness.run_for(Duration::from_secs(1));
+    assert!(harness.inventory().is_empty());
+    assert_eq!(harness.items_at(start + IVec3::X), vec![Item::new(ItemKind::Rock, 3)]);
+}
+
+#[test]
+fn picking_up_nothing_takes_nothing() {
+    let mut harness = Harness::new();
+    harness.tap(KeyCode::KeyG);
+    assert!(harness.inventory().is_empty());
+}
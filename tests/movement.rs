@@ -0,0 +1,65 @@
+mod common;
+
+use std::time::Duration;
+use bevy::prelude::*;
+use uahmt::ascii_world::Terrain;
+use common::{Harness, ARENA};
+
+#[test]
+fn tap_moves_one_cell() {
+    let mut harness = Harness::new();
+    let start = harness.player_pos();
+    harness.tap(KeyCode::KeyD);
+    assert_eq!(harness.player_pos(), start + IVec3::X);
+    let moves = harness.player_moves();
+    assert_eq!(moves.len(), 1, "{:?}", moves);
+    assert_eq!(moves[0].old_pos, start);
+    assert_eq!(moves[0].new_pos, start + IVec3::X);
+}
+
+/// The player starts at x = 0, y = 0, walking north or west has to go negative.
+#[test]
+fn moves_past_the_origin() {
+    let mut harness = Harness::new();
+    let start = harness.player_pos();
+    assert_eq!(start.truncate(), IVec2::ZERO);
+    harness.tap(KeyCode::KeyA);
+    harness.tap(KeyCode::KeyW);
+    assert_eq!(harness.player_pos(), start + IVec3::new(-1, -1, 0));
+    let moves = harness.player_moves();
+    assert_eq!(moves.len(), 2, "{:?}", moves);
+    assert_eq!(moves[1].new_pos, IVec3::new(-1, -1, start.z));
+}
+
+#[test]
+fn walls_block_movement() {
+    let mut harness = Harness::new();
+    let start = harness.player_pos();
+    harness.set_terrain(start + IVec3::X, Terrain::Wall);
+    harness.tap(KeyCode::KeyD);
+    assert_eq!(harness.player_pos(), start);
+    assert!(harness.player_moves().is_empty());
+}
+
+#[test]
+fn holding_a_key_keeps_walking_until_released() {
+    let mut harness = Harness::new();
+    let start = harness.player_pos();
+    harness.press(KeyCode::KeyS);
+    harness.run_for(Duration::from_millis(500));
+    harness.release(KeyCode::KeyS);
+    // The command queued last is still carried out.
+    harness.run_for(Duration::from_millis(200));
+    let moves = harness.player_moves();
+    assert!(moves.len() >= 3 && moves.len() < ARENA as usize, "{:?}", moves);
+    let mut pos = start;
+    for ev in moves.iter() {
+        assert_eq!(ev.old_pos, pos);
+        assert_eq!(ev.new_pos, pos + IVec3::Y);
+        pos = ev.new_pos;
+    }
+    assert_eq!(harness.player_pos(), pos);
+
+    harness.run_for(Duration::from_secs(1));
+    assert_eq!(harness.player_moves().len(), moves.len());
+}
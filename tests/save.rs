@@ -0,0 +1,33 @@
+mod common;
+
+use std::fs;
+use std::time::Duration;
+use bevy::prelude::*;
+use uahmt::item::{Item, ItemKind};
+use uahmt::save::{slot_path, LoadEvent, SaveSlot};
+use common::Harness;
+
+/// A quick save brings back where the player stood and what they carried.
+#[test]
+fn save_and_load_round_trip() {
+    let mut harness = Harness::new();
+    // Its own slot, so nothing else's save gets overwritten.
+    harness.app.world.resource_mut::<SaveSlot>().0 = String::from("round_trip_test");
+    let start = harness.player_pos();
+    harness.spawn_item(Item::new(ItemKind::Stick, 2), start);
+    harness.tap(KeyCode::KeyG);
+    harness.tap(KeyCode::KeyD);
+    let saved_pos = harness.player_pos();
+    assert_eq!(saved_pos, start + IVec3::X);
+    harness.tap(KeyCode::F5);
+    let path = slot_path("round_trip_test");
+    assert!(path.exists());
+
+    harness.tap(KeyCode::KeyS);
+    assert_ne!(harness.player_pos(), saved_pos);
+    harness.app.world.send_event(LoadEvent(path.clone()));
+    harness.run_for(Duration::from_secs(1));
+    let _ = fs::remove_file(&path);
+    assert_eq!(harness.player_pos(), saved_pos);
+    assert_eq!(harness.inventory(), vec![Item::new(ItemKind::Stick, 2)]);
+}
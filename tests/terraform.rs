@@ -0,0 +1,41 @@
+mod common;
+
+use bevy::prelude::*;
+use uahmt::ascii_world::Terrain;
+use uahmt::item::{Item, ItemKind};
+use common::Harness;
+
+/// Holding dig turns a move into digging out the cell, which leaves a rock behind.
+#[test]
+fn digs_out_a_wall() {
+    let mut harness = Harness::new();
+    let start = harness.player_pos();
+    harness.set_terrain(start + IVec3::X, Terrain::Wall);
+    harness.press(KeyCode::KeyX);
+    harness.tap(KeyCode::KeyD);
+    harness.release(KeyCode::KeyX);
+    assert_eq!(harness.terrain(start + IVec3::X), Some(Terrain::Air));
+    assert_eq!(harness.player_pos(), start);
+    assert_eq!(harness.items_at(start + IVec3::X), vec![Item::new(ItemKind::Rock, 1)]);
+}
+
+/// Holding build turns a move into building the selected structure, a wall by default,
+/// from carried materials.
+#[test]
+fn builds_a_wall_from_rocks() {
+    let mut harness = Harness::new();
+    let start = harness.player_pos();
+    harness.spawn_item(Item::new(ItemKind::Rock, 2), start);
+    harness.tap(KeyCode::KeyG);
+    harness.press(KeyCode::KeyB);
+    harness.tap(KeyCode::KeyD);
+    harness.release(KeyCode::KeyB);
+    assert_eq!(harness.terrain(start + IVec3::X), Some(Terrain::Wall));
+    assert!(harness.inventory().is_empty());
+
+    // Nothing left to build another one with.
+    harness.press(KeyCode::KeyB);
+    harness.tap(KeyCode::KeyA);
+    harness.release(KeyCode::KeyB);
+    assert_eq!(harness.terrain(start - IVec3::X), Some(Terrain::Air));
+}